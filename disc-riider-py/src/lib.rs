@@ -7,7 +7,7 @@ use std::{
 
 use binrw::{BinWrite, BinWriterExt};
 use disc_riider::{
-    builder::build_from_directory, structs::WiiPartType, Fst, FstNode, WiiIsoReader,
+    builder::build_from_directory, structs::WiiPartType, Fst, FstGlob, FstNode, WiiIsoReader,
     WiiPartitionReadInfo,
 };
 use pyo3::{exceptions, prelude::*};
@@ -165,32 +165,37 @@ impl WiiIsoExtractor {
     }
 
     pub fn remove_files_by_callback(&mut self, section: String, callback: Py<PyAny>) -> PyResult<()> {
-        fn should_remove_file(nodes: &mut Vec<FstNode>, dirstack: &mut Vec<String>, callback: &Py<PyAny>) {
-            nodes.retain_mut(|node| {
-                match node {
-                    FstNode::Directory { name, files } => {
-                        dirstack.push(name.clone());
-                        should_remove_file(files, dirstack, callback);
-                        dirstack.pop();
-                        true
-                    },
-                    FstNode::File { name, .. } => {
-                        dirstack.push(name.clone());
-                        let path = dirstack.join("/");
-                        dirstack.pop();
-                        let should_remove = Python::attach(|py| {
-                            callback.call1(py, (path,)).and_then(|obj| obj.is_truthy(py))
-                        }).unwrap_or(false);
-                        !should_remove
-                    }
-                }
-            });
+        let partition = self.get_partition(section)?;
+        let to_remove: Vec<String> = partition
+            .fst
+            .files()
+            .map(|entry| entry.path)
+            .filter(|path| {
+                Python::attach(|py| {
+                    callback
+                        .call1(py, (path.as_str(),))
+                        .and_then(|obj| obj.is_truthy(py))
+                })
+                .unwrap_or(false)
+            })
+            .collect();
+        for path in to_remove {
+            let _ = partition.fst.remove_node_path(&path);
         }
+        Ok(())
+    }
 
+    /// removes all files and directories matching the glob pattern, like `Stage/**/*.arc`,
+    /// and returns the removed paths
+    #[pyo3(signature = (section, pattern, ignore_case=false))]
+    pub fn remove_files_by_glob(
+        &mut self,
+        section: String,
+        pattern: &str,
+        ignore_case: bool,
+    ) -> PyResult<Vec<String>> {
         let partition = self.get_partition(section)?;
-        should_remove_file(partition.fst.get_entries_mut(), &mut Vec::new(), &callback);
-        
-        Ok(())
+        Ok(partition.fst.remove_glob(&FstGlob::new(pattern, ignore_case)))
     }
 
     pub fn extract_to(&mut self, path: PathBuf, callback: Py<PyAny>) -> PyResult<()> {
@@ -206,7 +211,7 @@ impl WiiIsoExtractor {
             create_dir_all(&section_path_disk)?;
 
             binrw_write_file(&section_path_disk.join("header.bin"), &disc_header)?;
-            fs::write(section_path_disk.join("region.bin"), region)?;
+            binrw_write_file(&section_path_disk.join("region.bin"), &region)?;
 
             partition
                 .partition_reader
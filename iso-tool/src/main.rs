@@ -1,5 +1,9 @@
 use clap::Parser;
-use disc_riider::{builder, structs::WiiPartType, WiiIsoReader};
+use disc_riider::{
+    builder,
+    structs::{PartitionDataMode, WiiPartType},
+    WiiIsoReader,
+};
 use std::{
     fs::{File, OpenOptions},
     path::PathBuf,
@@ -28,6 +32,11 @@ enum Commands {
     Rebuild {
         src_dir: PathBuf,
         dest_file: PathBuf,
+        #[clap(
+            long,
+            help = "don't encrypt or hash the partition data, only works in dolphin"
+        )]
+        plain: bool,
     },
 }
 
@@ -110,16 +119,30 @@ fn main() -> Result<(), MyError> {
             let mut part_reader = reader.open_partition(partition)?;
             part_reader.extract_system_files(&destination, &mut reader)?;
         }
-        Commands::Rebuild { src_dir, dest_file } => {
+        Commands::Rebuild {
+            src_dir,
+            dest_file,
+            plain,
+        } => {
             let mut f = OpenOptions::new()
                 .truncate(true)
                 .read(true)
                 .write(true)
                 .create(true)
                 .open(&dest_file)?;
-            builder::build_from_directory(&src_dir, &mut f, &mut |percent| -> () {
-                println!("rebuilding... {}%", percent);
-            })
+            let data_mode = if plain {
+                PartitionDataMode::Plain
+            } else {
+                PartitionDataMode::Encrypted
+            };
+            builder::build_from_directory_with_mode(
+                &src_dir,
+                &mut f,
+                data_mode,
+                &mut |percent| -> () {
+                    println!("rebuilding... {}%", percent);
+                },
+            )
             .map_err(|e| format!("{e:?}"))?;
         }
     }
@@ -0,0 +1,293 @@
+use std::io::{self, Cursor, Read, Seek};
+
+use binrw::{binrw, BinReaderExt, BinWriterExt};
+use md5::{Digest, Md5};
+use thiserror::Error;
+
+use crate::{
+    builder::{InvalidFilePath, IsoPartitionBuilder},
+    compression::{decompress_lz77, CompressionError, LZ77_MAGIC},
+    u8::{U8Archive, U8Error},
+};
+
+/// path of the banner in the data partition
+pub const BANNER_PATH: &str = "opening.bnr";
+
+const IMET_OFFSET: usize = 0x40;
+/// size of the zero prefix and the IMET header, which is hashed with the MD5 set to zero.
+/// The U8 archive follows directly
+const HEADER_SIZE: usize = 0x600;
+const MD5_OFFSET: usize = 0x5F0;
+pub const BANNER_NAME_LENGTH: usize = 42;
+const IMD5_MAGIC: &[u8; 4] = b"IMD5";
+const IMD5_HEADER_SIZE: usize = 0x20;
+
+#[derive(Error, Debug)]
+pub enum BannerError {
+    #[error("io error: {0}")]
+    IO(#[from] io::Error),
+    #[error("binrw error: {0}")]
+    BinRW(#[from] binrw::Error),
+    #[error("u8 error: {0}")]
+    U8(#[from] U8Error),
+    #[error("compression error: {0}")]
+    Compression(#[from] CompressionError),
+    #[error("{0}")]
+    InvalidDiscPath(#[from] InvalidFilePath),
+    #[error("banner is too short")]
+    Truncated,
+    #[error("name is longer than {BANNER_NAME_LENGTH} UTF-16 characters: {0}")]
+    NameTooLong(String),
+    #[error("{0} is missing in the banner")]
+    MissingFile(String),
+    #[error("{0} doesn't have an IMD5 header")]
+    InvalidImd5(String),
+    #[error("partition doesn't contain {BANNER_PATH}")]
+    MissingBanner,
+}
+
+/// Languages of the names in the banner, in the order they are stored
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BannerLanguage {
+    Japanese,
+    English,
+    German,
+    French,
+    Spanish,
+    Italian,
+    Dutch,
+    SimplifiedChinese,
+    TraditionalChinese,
+    Korean,
+}
+
+impl BannerLanguage {
+    pub const ALL: [BannerLanguage; 10] = [
+        BannerLanguage::Japanese,
+        BannerLanguage::English,
+        BannerLanguage::German,
+        BannerLanguage::French,
+        BannerLanguage::Spanish,
+        BannerLanguage::Italian,
+        BannerLanguage::Dutch,
+        BannerLanguage::SimplifiedChinese,
+        BannerLanguage::TraditionalChinese,
+        BannerLanguage::Korean,
+    ];
+}
+
+#[binrw]
+#[brw(big, magic = b"IMET")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImetHeader {
+    pub hash_size: u32,
+    pub unknown: u32,
+    /// sizes of icon.bin, banner.bin and sound.bin
+    pub sizes: [u32; 3],
+    pub flag: u32,
+    /// null terminated UTF-16 names, one per language
+    pub names: [[u16; BANNER_NAME_LENGTH]; 10],
+    #[brw(pad_before = 0x24C)]
+    pub md5: [u8; 16],
+}
+
+/// The banner shown in the Wii Menu, consisting of the IMET header with the
+/// localized names and a U8 archive with the icon, banner and sound
+#[derive(Clone, Debug)]
+pub struct Banner {
+    /// padding in front of the IMET header
+    prefix: Vec<u8>,
+    imet: ImetHeader,
+    archive_data: Vec<u8>,
+}
+
+impl Banner {
+    pub fn read(data: &[u8]) -> Result<Self, BannerError> {
+        if data.len() < HEADER_SIZE {
+            return Err(BannerError::Truncated);
+        }
+        let imet = Cursor::new(&data[IMET_OFFSET..]).read_be()?;
+        Ok(Self {
+            prefix: data[..IMET_OFFSET].to_vec(),
+            imet,
+            archive_data: data[HEADER_SIZE..].to_vec(),
+        })
+    }
+
+    pub fn get_imet(&self) -> &ImetHeader {
+        &self.imet
+    }
+
+    pub fn get_name(&self, language: BannerLanguage) -> String {
+        let name = &self.imet.names[language as usize];
+        let end = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+        String::from_utf16_lossy(&name[..end])
+    }
+
+    pub fn set_name(&mut self, language: BannerLanguage, name: &str) -> Result<(), BannerError> {
+        let encoded: Vec<u16> = name.encode_utf16().collect();
+        if encoded.len() > BANNER_NAME_LENGTH {
+            return Err(BannerError::NameTooLong(name.to_string()));
+        }
+        let dest = &mut self.imet.names[language as usize];
+        dest.fill(0);
+        dest[..encoded.len()].copy_from_slice(&encoded);
+        Ok(())
+    }
+
+    /// Sets the name of every language
+    pub fn set_all_names(&mut self, name: &str) -> Result<(), BannerError> {
+        for language in BannerLanguage::ALL {
+            self.set_name(language, name)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_archive(&self) -> Result<U8Archive, BannerError> {
+        Ok(U8Archive::read(&self.archive_data)?)
+    }
+
+    /// Reads a file from the meta directory, without the IMD5 header and decompressed
+    fn read_meta_file(&self, name: &str) -> Result<Vec<u8>, BannerError> {
+        let path = format!("meta/{name}");
+        let archive = self.get_archive()?;
+        let data = archive
+            .get_file_data(&path)
+            .ok_or_else(|| BannerError::MissingFile(path.clone()))?;
+        if !data.starts_with(IMD5_MAGIC) || data.len() < IMD5_HEADER_SIZE {
+            return Err(BannerError::InvalidImd5(path));
+        }
+        let data = &data[IMD5_HEADER_SIZE..];
+        if data.starts_with(LZ77_MAGIC) {
+            Ok(decompress_lz77(data)?)
+        } else {
+            Ok(data.to_vec())
+        }
+    }
+
+    /// The archive with the icon shown in the Wii Menu
+    pub fn get_icon(&self) -> Result<U8Archive, BannerError> {
+        Ok(U8Archive::read(&self.read_meta_file("icon.bin")?)?)
+    }
+
+    /// The archive with the banner shown when the disc channel is selected
+    pub fn get_banner(&self) -> Result<U8Archive, BannerError> {
+        Ok(U8Archive::read(&self.read_meta_file("banner.bin")?)?)
+    }
+
+    /// The sound played with the banner, usually a BNS file
+    pub fn get_sound(&self) -> Result<Vec<u8>, BannerError> {
+        self.read_meta_file("sound.bin")
+    }
+
+    /// Serializes the banner with an updated MD5 of the IMET header
+    pub fn to_bytes(&self) -> Result<Vec<u8>, BannerError> {
+        let mut imet = self.imet.clone();
+        imet.md5 = [0; 16];
+        let mut out = Cursor::new(self.prefix.clone());
+        out.set_position(IMET_OFFSET as u64);
+        out.write_be(&imet)?;
+        let mut out = out.into_inner();
+        let md5 = Md5::digest(&out[..HEADER_SIZE]);
+        out[MD5_OFFSET..MD5_OFFSET + 16].copy_from_slice(&md5);
+        out.extend_from_slice(&self.archive_data);
+        Ok(out)
+    }
+}
+
+/// Replaces the names of all languages in the banner of the partition
+pub fn replace_banner_names<RS: Read + Seek>(
+    partition: &mut IsoPartitionBuilder<RS>,
+    name: &str,
+) -> Result<(), BannerError> {
+    let data = partition
+        .read_file(BANNER_PATH)
+        .ok_or(BannerError::MissingBanner)??;
+    let mut banner = Banner::read(&data)?;
+    banner.set_all_names(name)?;
+    partition.set_file(BANNER_PATH, banner.to_bytes()?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use md5::{Digest, Md5};
+
+    use crate::u8::U8Archive;
+
+    use super::{Banner, BannerLanguage};
+
+    fn imd5(data: &[u8]) -> Vec<u8> {
+        let mut out = b"IMD5".to_vec();
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        out.resize(0x20, 0);
+        out.extend_from_slice(data);
+        out
+    }
+
+    #[test]
+    pub fn test_banner_names() {
+        let mut icon = U8Archive::new();
+        icon.add_file("arc/timg/icon.tpl", vec![1, 2, 3]).unwrap();
+        let icon = icon.to_bytes().unwrap();
+        // LZ77 with only literals
+        let mut compressed = b"LZ77".to_vec();
+        compressed.extend_from_slice(&((icon.len() as u32) << 8 | 0x10).to_le_bytes());
+        for chunk in icon.chunks(8) {
+            compressed.push(0);
+            compressed.extend_from_slice(chunk);
+        }
+        let mut archive = U8Archive::new();
+        archive
+            .add_file("meta/icon.bin", imd5(&compressed))
+            .unwrap();
+        archive.add_file("meta/sound.bin", imd5(b"BNS ")).unwrap();
+
+        // layout of a retail opening.bnr: 0x40 zero bytes, the IMET header up to 0x600
+        // with the MD5 of 0..0x600 at 0x5F0, then the archive
+        let mut data = vec![0; 0x600];
+        data[0x40..0x44].copy_from_slice(b"IMET");
+        data[0x44..0x48].copy_from_slice(&0x600u32.to_be_bytes());
+        data[0x48..0x4C].copy_from_slice(&3u32.to_be_bytes());
+        // English name at 0x5C + 0x54
+        for (i, c) in "Skyward".encode_utf16().enumerate() {
+            data[0xB0 + i * 2..][..2].copy_from_slice(&c.to_be_bytes());
+        }
+        let md5 = Md5::digest(&data);
+        data[0x5F0..0x600].copy_from_slice(&md5);
+        data.extend_from_slice(&archive.to_bytes().unwrap());
+        let mut banner = Banner::read(&data).unwrap();
+        assert_eq!(banner.get_imet().hash_size, 0x600);
+        assert_eq!(banner.get_name(BannerLanguage::English), "Skyward");
+        assert_eq!(banner.get_name(BannerLanguage::Japanese), "");
+        assert_eq!(banner.to_bytes().unwrap(), data);
+        banner.set_all_names("Randomizer - seed XYZ").unwrap();
+        banner
+            .set_name(BannerLanguage::Japanese, "ゼルダの伝説")
+            .unwrap();
+        assert!(banner
+            .set_name(BannerLanguage::German, &"a".repeat(43))
+            .is_err());
+
+        let bytes = banner.to_bytes().unwrap();
+        assert_eq!(&bytes[0x600..0x604], b"\x55\xAA\x38\x2D");
+        let mut header = bytes[..0x600].to_vec();
+        header[0x5F0..].fill(0);
+        assert_eq!(&bytes[0x5F0..0x600], Md5::digest(&header).as_slice());
+        let banner = Banner::read(&bytes).unwrap();
+        assert_eq!(
+            banner.get_name(BannerLanguage::Korean),
+            "Randomizer - seed XYZ"
+        );
+        assert_eq!(banner.get_name(BannerLanguage::Japanese), "ゼルダの伝説");
+        assert_eq!(
+            banner
+                .get_icon()
+                .unwrap()
+                .get_file_data("arc/timg/icon.tpl"),
+            Some(&[1, 2, 3][..])
+        );
+        assert_eq!(banner.get_sound().unwrap(), b"BNS ");
+        assert!(banner.get_banner().is_err());
+    }
+}
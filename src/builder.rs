@@ -1,10 +1,12 @@
 use std::{
     borrow::{Borrow, Cow},
-    convert::Infallible,
+    collections::HashMap,
     error::Error,
     fs::{File, OpenOptions},
     io::{self, Cursor, Read, Seek, SeekFrom, Write},
+    iter::Peekable,
     path::{Path, PathBuf},
+    vec,
 };
 
 use binrw::{BinReaderExt, BinWriterExt};
@@ -12,13 +14,18 @@ use sha1::{Digest, Sha1};
 
 use crate::{
     dir_reader::{self, BuildDirError},
+    fakesign::{fakesign_ticket, fakesign_tmd, FakesignError},
     fst::FstToBytesError,
+    layout::{LayoutError, LayoutPolicy, DEFAULT_FILE_ALIGNMENT},
     reader_writer::WiiEncryptedReadWriteStream,
+    signature::{verify_ticket, SignatureStatus},
     structs::{
-        Certificate, DiscHeader, Ticket, WiiPartTableEntry, WiiPartType, WiiPartitionHeader, TMD,
+        ApploaderHeader, Bi2, Certificate, DiscHeader, PartitionDataMode, Region, Ticket,
+        WiiPartTableEntry, WiiPartType, WiiPartitionHeader, APPLOADER_HEADER_SIZE, BI2_SIZE,
+        REGION_FREE, TMD,
     },
-    Fst, FstNode, FstToBytes, IOWindow, WiiIsoReader, WiiPartitionReadInfo, GROUP_DATA_SIZE,
-    GROUP_SIZE,
+    Fst, FstNode, FstToBytes, IOWindow, WiiIsoReader, WiiPartitionReadInfo, BLOCK_SIZE,
+    GROUP_DATA_SIZE, GROUP_SIZE,
 };
 
 #[inline]
@@ -38,6 +45,14 @@ pub enum PartitionAddError<E: Error> {
     BinRW(#[from] binrw::Error),
     #[error("fst build failed: {0}")]
     Fst(#[from] FstToBytesError),
+    #[error("fakesigning failed: {0}")]
+    Fakesign(#[from] FakesignError),
+    #[error("bi2 has size {0:#x} instead of 0x2000")]
+    InvalidBi2Size(usize),
+    #[error("apploader has size {actual:#x}, but its header needs {expected:#x}")]
+    InvalidApploaderSize { expected: u64, actual: usize },
+    #[error("file layout failed: {0}")]
+    Layout(#[from] LayoutError),
 }
 
 // 0: disc header
@@ -55,6 +70,289 @@ pub enum PartitionAddError<E: Error> {
 //  fst
 //  data
 
+/// writer for the data area of a partition, plain partitions don't have any hashes
+enum PartitionDataWriter<'a, RS: Read + Write + Seek> {
+    Hashed(WiiEncryptedReadWriteStream<'a, RS>),
+    Plain(IOWindow<&'a mut RS>),
+}
+
+impl<'a, RS: Read + Write + Seek> PartitionDataWriter<'a, RS> {
+    fn take_h3(&mut self) -> Box<[u8; 0x18000]> {
+        match self {
+            Self::Hashed(writer) => writer.take_h3().unwrap(),
+            Self::Plain(_) => vec![0; 0x18000].into_boxed_slice().try_into().unwrap(),
+        }
+    }
+}
+
+impl<'a, RS: Read + Write + Seek> Write for PartitionDataWriter<'a, RS> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Hashed(writer) => writer.write(buf),
+            Self::Plain(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Hashed(writer) => writer.flush(),
+            Self::Plain(writer) => writer.flush(),
+        }
+    }
+}
+
+impl<'a, RS: Read + Write + Seek> Seek for PartitionDataWriter<'a, RS> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Self::Hashed(writer) => writer.seek(pos),
+            Self::Plain(writer) => writer.seek(pos),
+        }
+    }
+}
+
+/// File of the FST with references to its offset and length, which are set when writing
+struct LayoutFile<'f> {
+    path: String,
+    parts: Vec<String>,
+    offset: &'f mut u64,
+    length: &'f mut u32,
+}
+
+/// File that is written at its offset in the original partition
+struct PinnedFile<'f> {
+    file: LayoutFile<'f>,
+    original_offset: u64,
+    original_length: u32,
+    /// files that don't fit at the original offset get moved to the end instead of
+    /// failing, they can use the space up until the next pinned file
+    relocatable: bool,
+    max_length: u64,
+    /// another pinned file has the same original offset and length
+    shared: bool,
+}
+
+/// Writes the data of files only forward, gaps are filled with zeros
+struct FileDataWriter<'w, W: Write, C: FnMut(u8)> {
+    writer: &'w mut W,
+    /// position the writer is at
+    written: u64,
+    /// end of the last file including padding and slack, nothing can be placed before
+    reserved_end: u64,
+    processed_files: usize,
+    total_files: usize,
+    processed_file_bytes: usize,
+    /// 0 if the size is unknown, then progress is reported per file
+    total_bytes: usize,
+    progress_cb: &'w mut C,
+    /// offset and length of written data by its SHA-1, if files are deduplicated
+    file_hashes: Option<HashMap<[u8; 20], (u64, u32)>>,
+    /// SHA-1 of data written for pinned files that share their original location
+    shared_locations: HashMap<(u64, u32), [u8; 20]>,
+}
+
+impl<'w, W: Write, C: FnMut(u8)> FileDataWriter<'w, W, C> {
+    fn fill_to(&mut self, position: u64) -> io::Result<()> {
+        static ZEROS: [u8; 0x8000] = [0; 0x8000];
+        while self.written < position {
+            let bytes_to_write = ((position - self.written) as usize).min(ZEROS.len());
+            self.writer.write_all(&ZEROS[..bytes_to_write])?;
+            self.written += bytes_to_write as u64;
+        }
+        Ok(())
+    }
+
+    fn report_bytes(&mut self, bytes: usize) {
+        if self.total_bytes != 0 {
+            self.processed_file_bytes += bytes;
+            let done_percent =
+                ((self.processed_file_bytes as f64) / (self.total_bytes as f64) * 100f64) as u8;
+            (self.progress_cb)(done_percent);
+        }
+    }
+
+    fn report_file(&mut self) {
+        self.processed_files += 1;
+        if self.total_bytes == 0 {
+            let done_percent =
+                ((self.processed_files as f64) / (self.total_files as f64) * 100f64) as u8;
+            (self.progress_cb)(done_percent);
+        }
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        self.fill_to(offset)?;
+        for batch in data.chunks(0x1_000_000) {
+            self.writer.write_all(batch)?;
+            self.report_bytes(batch.len());
+        }
+        self.written += data.len() as u64;
+        self.report_file();
+        Ok(())
+    }
+
+    /// Remembers where the data was written, so later identical files can share it
+    fn add_file_hash(&mut self, hash: Option<[u8; 20]>, offset: u64, length: u32) {
+        if let (Some(file_hashes), Some(hash)) = (&mut self.file_hashes, hash) {
+            file_hashes.entry(hash).or_insert((offset, length));
+        }
+    }
+
+    /// Offset and length of already written data identical to the hashed data
+    fn find_existing(&self, hash: Option<[u8; 20]>) -> Option<(u64, u32)> {
+        self.file_hashes.as_ref()?.get(&hash?).copied()
+    }
+
+    /// Whether the next pinned file starts before the end of a file that is placed next
+    fn has_pinned_in_front(
+        &self,
+        pinned: &mut Peekable<vec::IntoIter<PinnedFile<'_>>>,
+        alignment: u64,
+        size: u64,
+    ) -> bool {
+        let end = align_next(self.reserved_end, alignment) + size;
+        pinned
+            .peek()
+            .is_some_and(|pinned_file| pinned_file.original_offset < end)
+    }
+
+    /// Writes the file after the previous one, pinned files that would overlap are
+    /// written first. Relocated pinned files are added to `relocated`
+    fn write_file<'f, E: Error, P: WiiPartitionDefinition<E>>(
+        &mut self,
+        partition_def: &mut P,
+        policy: &LayoutPolicy,
+        file: LayoutFile<'f>,
+        pinned: &mut Peekable<vec::IntoIter<PinnedFile<'f>>>,
+        relocated: &mut Vec<LayoutFile<'f>>,
+    ) -> Result<(), PartitionAddError<E>> {
+        let alignment = policy.get_alignment(&file.path);
+        let slack = policy.get_slack(&file.path);
+        let (data, padding) = partition_def.get_file_data(&file.parts)?;
+        let hash: Option<[u8; 20]> = self
+            .file_hashes
+            .is_some()
+            .then(|| Sha1::digest(&data).into());
+        let size = data.len() as u64 + padding as u64 + slack;
+        if self.find_existing(hash).is_some() || !self.has_pinned_in_front(pinned, alignment, size)
+        {
+            self.place_file(file, &data, hash, alignment, size)?;
+            return Ok(());
+        }
+        // pinned files in front need the partition definition to get their data,
+        // so keep a copy instead of reading this file again for every one of them
+        let data = data.into_owned();
+        while self.find_existing(hash).is_none()
+            && self.has_pinned_in_front(pinned, alignment, size)
+        {
+            let pinned_file = pinned.next().unwrap();
+            let pinned_slack = policy.get_slack(&pinned_file.file.path);
+            relocated.extend(self.write_pinned(partition_def, pinned_file, pinned_slack)?);
+        }
+        self.place_file(file, &data, hash, alignment, size)?;
+        Ok(())
+    }
+
+    /// Writes the data after the previous file, or points to identical data
+    fn place_file(
+        &mut self,
+        file: LayoutFile<'_>,
+        data: &[u8],
+        hash: Option<[u8; 20]>,
+        alignment: u64,
+        size: u64,
+    ) -> io::Result<()> {
+        if let Some((offset, length)) = self.find_existing(hash) {
+            // identical data was already written, only the FST entry points to it
+            *file.offset = offset;
+            *file.length = length;
+            self.report_bytes(data.len());
+            self.report_file();
+            return Ok(());
+        }
+        let start = align_next(self.reserved_end, alignment);
+        self.write_at(start, data)?;
+        *file.offset = start;
+        *file.length = data.len() as u32;
+        self.reserved_end = start + size;
+        self.add_file_hash(hash, start, data.len() as u32);
+        Ok(())
+    }
+
+    /// Writes the file at its original offset, returns it if it has to be relocated
+    fn write_pinned<'f, E: Error, P: WiiPartitionDefinition<E>>(
+        &mut self,
+        partition_def: &mut P,
+        pinned_file: PinnedFile<'f>,
+        slack: u64,
+    ) -> Result<Option<LayoutFile<'f>>, PartitionAddError<E>> {
+        let PinnedFile {
+            file,
+            original_offset,
+            original_length,
+            relocatable,
+            max_length,
+            shared,
+        } = pinned_file;
+        let (data, padding) = partition_def.get_file_data(&file.parts)?;
+        if data.is_empty() && padding == 0 {
+            // empty files don't occupy any space, so they can't overlap with anything
+            *file.offset = original_offset;
+            *file.length = 0;
+            self.report_file();
+            return Ok(None);
+        }
+        let hash: Option<[u8; 20]> =
+            (shared || self.file_hashes.is_some()).then(|| Sha1::digest(&data).into());
+        if shared {
+            let location = (original_offset, data.len() as u32);
+            if self.shared_locations.get(&location) == hash.as_ref() {
+                // the source already shared this data with a file that is written
+                *file.offset = original_offset;
+                *file.length = data.len() as u32;
+                self.report_bytes(data.len());
+                self.report_file();
+                return Ok(None);
+            }
+        }
+        if relocatable {
+            if data.len() as u64 > max_length || original_offset < self.reserved_end {
+                return Ok(Some(file));
+            }
+        } else {
+            if data.len() > original_length as usize {
+                return Err(LayoutError::PinnedFileGrew {
+                    path: file.path,
+                    original_length,
+                    new_length: data.len() as u64,
+                }
+                .into());
+            }
+            if original_offset < self.reserved_end {
+                return Err(LayoutError::PinnedFileOverlap {
+                    path: file.path,
+                    offset: original_offset,
+                    position: self.reserved_end,
+                }
+                .into());
+            }
+        }
+        self.write_at(original_offset, &data)?;
+        *file.offset = original_offset;
+        *file.length = data.len() as u32;
+        // pinned files always keep their own copy, but others can share it
+        self.add_file_hash(hash, original_offset, data.len() as u32);
+        if let (true, Some(hash)) = (shared, hash) {
+            self.shared_locations
+                .insert((original_offset, data.len() as u32), hash);
+        }
+        // keep the original space reserved
+        self.reserved_end = original_offset
+            + (original_length as u64).max(data.len() as u64 + padding as u64)
+            + slack;
+        Ok(None)
+    }
+}
+
 /// Trait to implement for building a wii partition.
 pub trait WiiPartitionDefinition<E: Error> {
     /// returns the header of the partition which looks like a disc header
@@ -77,32 +375,215 @@ pub trait WiiPartitionDefinition<E: Error> {
         &'a mut self,
         path: &Vec<String>,
     ) -> Result<(Cow<'a, [u8]>, u32), PartitionAddError<E>>;
+
+    /// returns the offset and length of the file in the partition this one is based on,
+    /// used to pin files to their original offset with a [`LayoutPolicy`]
+    fn get_original_location(&mut self, _path: &[String]) -> Option<(u64, u32)> {
+        None
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum TitleOverrideError {
+    #[error("game id has to be 6 uppercase alphanumeric characters: {0:?}")]
+    InvalidGameId(String),
+    #[error("game title is longer than 63 bytes: {0}")]
+    TitleTooLong(String),
+}
+
+/// Replaces the game ID (and optionally the game title) of a disc while building,
+/// so that it can be installed next to the original game
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TitleOverride {
+    game_id: [u8; 6],
+    game_title: Option<String>,
 }
 
+impl TitleOverride {
+    pub fn new(game_id: &str, game_title: Option<String>) -> Result<Self, TitleOverrideError> {
+        let game_id_bytes: [u8; 6] = game_id
+            .as_bytes()
+            .try_into()
+            .ok()
+            .filter(|id: &[u8; 6]| {
+                id.iter()
+                    .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+            })
+            .ok_or_else(|| TitleOverrideError::InvalidGameId(game_id.to_string()))?;
+        if let Some(title) = &game_title {
+            // needs to fit into 64 bytes including the null terminator
+            if title.len() >= 64 {
+                return Err(TitleOverrideError::TitleTooLong(title.clone()));
+            }
+        }
+        Ok(Self {
+            game_id: game_id_bytes,
+            game_title,
+        })
+    }
+
+    pub fn get_game_id(&self) -> &[u8; 6] {
+        &self.game_id
+    }
+
+    pub fn get_game_title(&self) -> Option<&str> {
+        self.game_title.as_deref()
+    }
+
+    fn apply_to_header(&self, header: &mut DiscHeader) {
+        header.game_id = self.game_id;
+        if let Some(title) = &self.game_title {
+            header.game_title.clone_from(title);
+        }
+    }
+
+    /// the title ID of disc games is 00010000 followed by the first 4 characters of the game ID
+    fn apply_to_title(&self, ticket: &mut Ticket, tmd: &mut TMD) {
+        ticket.title_id[4..].copy_from_slice(&self.game_id[..4]);
+        tmd.title_id_minor.copy_from_slice(&self.game_id[..4]);
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("region letter has to be an uppercase ASCII letter: {0:?}")]
+pub struct InvalidRegionLetter(pub char);
+
+/// Makes a disc bootable on consoles of all regions while building, by setting the
+/// region info and the bi2 region and optionally replacing the region letter of the game ID
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegionFree {
+    game_id_region: Option<u8>,
+}
+
+impl RegionFree {
+    /// `game_id_region` replaces the 4th character of the game ID, for example `'E'`,
+    /// which also changes the title ID, so the ticket and TMD get fakesigned
+    pub fn new(game_id_region: Option<char>) -> Result<Self, InvalidRegionLetter> {
+        let game_id_region = match game_id_region {
+            Some(letter) if letter.is_ascii_uppercase() => Some(letter as u8),
+            Some(letter) => return Err(InvalidRegionLetter(letter)),
+            None => None,
+        };
+        Ok(Self { game_id_region })
+    }
+
+    pub fn get_game_id_region(&self) -> Option<char> {
+        self.game_id_region.map(char::from)
+    }
+
+    fn apply_to_header(&self, header: &mut DiscHeader) {
+        if let Some(letter) = self.game_id_region {
+            header.game_id[3] = letter;
+        }
+    }
+
+    fn apply_to_title(&self, ticket: &mut Ticket, tmd: &mut TMD) {
+        if let Some(letter) = self.game_id_region {
+            ticket.title_id[7] = letter;
+            tmd.title_id_minor[3] = letter;
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("partition data mode can't be changed after a partition was added")]
+pub struct PartitionDataModeLocked;
+
+#[derive(thiserror::Error, Debug)]
+#[error("title override can't be changed after a partition was added")]
+pub struct TitleOverrideLocked;
+
+#[derive(thiserror::Error, Debug)]
+#[error("region free setting can't be changed after a partition was added")]
+pub struct RegionFreeLocked;
+
 pub struct WiiDiscBuilder<WS: Read + Write + Seek> {
     file: WS,
     disc_header: DiscHeader,
-    region: [u8; 32],
+    region: Region,
     current_data_offset: u64,
     partitions: Vec<WiiPartTableEntry>,
+    title_override: Option<TitleOverride>,
+    region_free: Option<RegionFree>,
+    partition_data_mode: PartitionDataMode,
+    layout_policy: LayoutPolicy,
+    deduplicate_files: bool,
 }
 
 impl<WS: Read + Write + Seek> WiiDiscBuilder<WS> {
-    pub fn create(file: WS, disc_header: DiscHeader, region: [u8; 32]) -> Self {
+    pub fn create(file: WS, disc_header: DiscHeader, region: Region) -> Self {
         Self {
             file,
             disc_header,
             region,
             current_data_offset: 0x50000,
             partitions: Vec::new(),
+            title_override: None,
+            region_free: None,
+            partition_data_mode: PartitionDataMode::Encrypted,
+            layout_policy: LayoutPolicy::default(),
+            deduplicate_files: false,
+        }
+    }
+
+    /// Sets how the data of all partitions is stored, unencrypted and plain partitions
+    /// are only supported by Dolphin and dev tooling, but are much faster to build.
+    /// The disc header flags are set accordingly, since they apply to the whole disc
+    /// the mode can't be changed after the first partition was added
+    pub fn set_partition_data_mode(
+        &mut self,
+        mode: PartitionDataMode,
+    ) -> Result<(), PartitionDataModeLocked> {
+        if !self.partitions.is_empty() && mode != self.partition_data_mode {
+            return Err(PartitionDataModeLocked);
+        }
+        self.partition_data_mode = mode;
+        Ok(())
+    }
+
+    /// Rewrites the game ID in the disc headers, ticket and TMD of all partitions,
+    /// the modified ticket and TMD are fakesigned. Since it also applies to the header
+    /// of the whole disc it can't be changed after the first partition was added
+    pub fn set_title_override(
+        &mut self,
+        title_override: Option<TitleOverride>,
+    ) -> Result<(), TitleOverrideLocked> {
+        if !self.partitions.is_empty() && title_override != self.title_override {
+            return Err(TitleOverrideLocked);
+        }
+        self.title_override = title_override;
+        Ok(())
+    }
+
+    /// Makes the disc region-free, applies to the region info and all partitions,
+    /// so it can't be changed after the first partition was added
+    pub fn set_region_free(
+        &mut self,
+        region_free: Option<RegionFree>,
+    ) -> Result<(), RegionFreeLocked> {
+        if !self.partitions.is_empty() && region_free != self.region_free {
+            return Err(RegionFreeLocked);
         }
+        self.region_free = region_free;
+        Ok(())
+    }
+
+    /// Sets where the data of files is placed in partitions added after this call
+    pub fn set_layout_policy(&mut self, layout_policy: LayoutPolicy) {
+        self.layout_policy = layout_policy;
+    }
+
+    /// Hashes the data of all files in partitions added after this call, files with
+    /// identical data share a single copy. Pinned files are always written
+    pub fn set_deduplicate_files(&mut self, deduplicate_files: bool) {
+        self.deduplicate_files = deduplicate_files;
     }
 
     pub fn add_partition<P, E, C>(
         &mut self,
         part_type: WiiPartType,
-        ticket: Ticket,
-        tmd: TMD,
+        mut ticket: Ticket,
+        mut tmd: TMD,
         cert_chain: [Certificate; 3],
         partition_def: &mut P,
         progress_cb: &mut C,
@@ -113,6 +594,21 @@ impl<WS: Read + Write + Seek> WiiDiscBuilder<WS> {
         C: FnMut(u8),
     {
         progress_cb(0);
+        if let Some(title_override) = &self.title_override {
+            // the title key is encrypted with the title ID when the ticket is written,
+            // so it's automatically valid for the new ID
+            title_override.apply_to_title(&mut ticket, &mut tmd);
+        }
+        if let Some(region_free) = &self.region_free {
+            region_free.apply_to_title(&mut ticket, &mut tmd);
+        }
+        // a modified ticket doesn't pass the signature check anymore
+        if !matches!(
+            verify_ticket(&ticket, &cert_chain),
+            Ok(SignatureStatus::Official | SignatureStatus::Fakesigned)
+        ) {
+            fakesign_ticket(&mut ticket)?;
+        }
         let part_data_off = self.current_data_offset;
         let mut partition_window = IOWindow::new(&mut self.file, part_data_off, None);
         self.partitions.push(WiiPartTableEntry {
@@ -146,38 +642,72 @@ impl<WS: Read + Write + Seek> WiiDiscBuilder<WS> {
         // global hash table at 0x8000, encrypted data starts at 0x20000
         // let mut h3: Box<[u8; 0x18000]> = vec![0u8; 0x18000].into_boxed_slice().try_into().unwrap();
         // now we write encrypted data
-        let mut crypto_writer = WiiEncryptedReadWriteStream::create_write(
-            &mut partition_window,
-            0x20000,
-            part_header.ticket.title_key,
-            None,
-            0,
-        );
+        let data_mode = self.partition_data_mode;
+        let mut crypto_writer = match data_mode {
+            PartitionDataMode::Plain => {
+                PartitionDataWriter::Plain(IOWindow::new(&mut partition_window, 0x20000, None))
+            }
+            PartitionDataMode::Encrypted | PartitionDataMode::Unencrypted => {
+                PartitionDataWriter::Hashed(WiiEncryptedReadWriteStream::create_write(
+                    &mut partition_window,
+                    0x20000,
+                    (data_mode == PartitionDataMode::Encrypted)
+                        .then_some(part_header.ticket.title_key),
+                    None,
+                    0,
+                ))
+            }
+        };
         let source_fst = partition_def.get_fst()?;
         let mut total_files = 0;
         // TODO: currently use total_bytes = 0 as an indicator that the size is unknown
         let mut total_bytes = 0;
-        source_fst
-            .callback_all_files::<Infallible, _>(&mut |_, node| {
-                match node {
-                    FstNode::File { length, .. } => {
-                        total_files += 1;
-                        total_bytes += *length as usize;
-                    }
-                    _ => (),
-                }
-                Ok(())
-            })
-            .unwrap();
+        for entry in source_fst.files() {
+            if let FstNode::File { length, .. } = entry.node {
+                total_files += 1;
+                total_bytes += *length as usize;
+            }
+        }
         let uses_file_byte_progress = total_bytes != 0;
-        let mut fst = FstToBytes::try_from(source_fst)?;
+        let mut fst = FstToBytes::try_from_fix_ordering(source_fst)?;
         let mut part_disc_header = partition_def.get_disc_header()?;
+        if let Some(title_override) = &self.title_override {
+            title_override.apply_to_header(&mut part_disc_header);
+        }
+        if let Some(region_free) = &self.region_free {
+            region_free.apply_to_header(&mut part_disc_header);
+        }
+        part_disc_header.set_partition_data_mode(data_mode);
+        let mut bi2 = partition_def.get_bi2()?;
+        if bi2.len() != BI2_SIZE {
+            return Err(PartitionAddError::InvalidBi2Size(bi2.len()));
+        }
+        if self.region_free.is_some() {
+            let mut parsed_bi2: Bi2 = Cursor::new(&*bi2).read_be()?;
+            parsed_bi2.region_code = REGION_FREE;
+            let mut buf = Vec::with_capacity(BI2_SIZE);
+            Cursor::new(&mut buf).write_be(&parsed_bi2)?;
+            bi2 = Cow::Owned(buf);
+        }
         crypto_writer.seek(SeekFrom::Start(0x440))?;
-        crypto_writer.write_all(&partition_def.get_bi2()?)?;
+        crypto_writer.write_all(&bi2)?;
 
         // write apploader (always at the same address)
+        let apploader = partition_def.get_apploader()?;
+        let apploader_header: ApploaderHeader = Cursor::new(&*apploader).read_be()?;
+        let apploader_size = APPLOADER_HEADER_SIZE
+            .checked_add(apploader_header.size1)
+            .and_then(|size| size.checked_add(apploader_header.size2));
+        if apploader_size.is_none_or(|size| apploader.len() < size as usize) {
+            return Err(PartitionAddError::InvalidApploaderSize {
+                expected: APPLOADER_HEADER_SIZE as u64
+                    + apploader_header.size1 as u64
+                    + apploader_header.size2 as u64,
+                actual: apploader.len(),
+            });
+        }
         crypto_writer.seek(SeekFrom::Start(0x2440))?;
-        crypto_writer.write_all(&partition_def.get_apploader()?)?;
+        crypto_writer.write_all(&apploader)?;
 
         // write dol
         part_disc_header.dol_off = align_next(crypto_writer.stream_position()?, 0x20).into();
@@ -198,45 +728,109 @@ impl<WS: Read + Write + Seek> WiiDiscBuilder<WS> {
         // now we can actually write the data
         let data_start = align_next(crypto_writer.stream_position()?, 0x40);
         crypto_writer.seek(SeekFrom::Start(data_start))?;
-        let mut processed_files = 0;
-        let mut processed_file_bytes = 0;
-        fst.callback_all_files_mut::<PartitionAddError<E>, _>(&mut |path, offset, size| {
-            processed_files += 1;
-            *offset = crypto_writer.stream_position()?;
-            let (data, padding) = partition_def.get_file_data(path)?;
-            let mut remaining_data = data.as_ref();
-            *size = remaining_data.len() as u32;
-            while remaining_data.len() > 0 {
-                let bytes_to_write = remaining_data.len().min(0x1_000_000);
-                let batch;
-                (batch, remaining_data) = remaining_data.split_at(bytes_to_write);
-                crypto_writer.write_all(batch)?;
-                if uses_file_byte_progress {
-                    processed_file_bytes += bytes_to_write;
-                    let done_percent =
-                        ((processed_file_bytes as f64) / (total_bytes as f64) * 100f64) as u8;
-                    progress_cb(done_percent);
+        let policy = &self.layout_policy;
+        let mut pinned = Vec::new();
+        let mut files = Vec::new();
+        for (path, offset, length) in fst.files_mut() {
+            let parts: Vec<String> = path.split('/').map(String::from).collect();
+            let original = if policy.is_pinned(&path) {
+                partition_def
+                    .get_original_location(&parts)
+                    .map(|location| (location, false))
+            } else if policy.get_preserve_offsets() && *offset != 0 {
+                // offset 0 is the disc header, so these are new files
+                Some(((*offset, *length), true))
+            } else {
+                None
+            };
+            let file = LayoutFile {
+                path,
+                parts,
+                offset,
+                length,
+            };
+            match original {
+                Some(((original_offset, original_length), relocatable)) => {
+                    pinned.push(PinnedFile {
+                        file,
+                        original_offset,
+                        original_length,
+                        relocatable,
+                        max_length: u64::MAX,
+                        shared: false,
+                    })
                 }
+                None => files.push((file.path.clone(), file)),
             }
-            const ZEROS: [u8; 0x40] = [0; 0x40];
-            let mut current_position = crypto_writer.stream_position()?;
-            let next_start = align_next(current_position + padding as u64, 0x40);
-            while current_position < next_start {
-                let bytes_to_write = ((next_start - current_position) as usize).min(ZEROS.len());
-                current_position += bytes_to_write as u64;
-                crypto_writer.write_all(&ZEROS[..bytes_to_write])?;
+        }
+        let location =
+            |pinned_file: &PinnedFile| (pinned_file.original_offset, pinned_file.original_length);
+        pinned.sort_by_key(location);
+        // files the source shares can keep sharing their data if it didn't change
+        for i in 1..pinned.len() {
+            if location(&pinned[i - 1]) == location(&pinned[i]) {
+                pinned[i - 1].shared = true;
+                pinned[i].shared = true;
             }
-            if !uses_file_byte_progress {
-                let done_percent = ((processed_files as f64) / (total_files as f64) * 100f64) as u8;
-                progress_cb(done_percent);
+        }
+        // preserved files can grow up until the next pinned file
+        for i in 0..pinned.len() {
+            let original_offset = pinned[i].original_offset;
+            if let Some(next) = pinned[i + 1..]
+                .iter()
+                .find(|next| next.original_offset > original_offset)
+            {
+                pinned[i].max_length = next.original_offset - original_offset;
             }
-            Ok(())
-        })?;
+        }
+        let mut pinned = pinned.into_iter().peekable();
+        policy.sort_files(&mut files);
+        let mut file_writer = FileDataWriter {
+            writer: &mut crypto_writer,
+            written: data_start,
+            reserved_end: data_start,
+            processed_files: 0,
+            total_files,
+            processed_file_bytes: 0,
+            total_bytes: if uses_file_byte_progress {
+                total_bytes
+            } else {
+                0
+            },
+            progress_cb,
+            file_hashes: self.deduplicate_files.then(HashMap::new),
+            shared_locations: HashMap::new(),
+        };
+        let mut relocated = Vec::new();
+        for (_, file) in files {
+            file_writer.write_file(partition_def, policy, file, &mut pinned, &mut relocated)?;
+        }
+        for pinned_file in pinned.by_ref() {
+            let pinned_slack = policy.get_slack(&pinned_file.file.path);
+            relocated.extend(file_writer.write_pinned(partition_def, pinned_file, pinned_slack)?);
+        }
+        // files that don't fit at their original offset anymore go to the end
+        for file in relocated {
+            file_writer.write_file(partition_def, policy, file, &mut pinned, &mut Vec::new())?;
+        }
+        // slack at the end has to be part of the partition
+        let data_end = align_next(file_writer.reserved_end, DEFAULT_FILE_ALIGNMENT);
+        file_writer.fill_to(data_end)?;
 
-        // align total size to next full group
-        let groups = (crypto_writer.stream_position()? + GROUP_DATA_SIZE - 1) / GROUP_DATA_SIZE;
-        let total_size = groups * GROUP_DATA_SIZE;
-        let total_encrypted_size = groups * GROUP_SIZE;
+        let (total_size, total_encrypted_size) = if data_mode == PartitionDataMode::Plain {
+            // no groups without hashes, only pad to the next block
+            let data_end = crypto_writer.stream_position()?;
+            let total_size = align_next(data_end, BLOCK_SIZE);
+            if total_size > data_end {
+                crypto_writer.seek(SeekFrom::Start(total_size - 1))?;
+                crypto_writer.write_all(&[0])?;
+            }
+            (total_size, total_size)
+        } else {
+            // align total size to next full group
+            let groups = crypto_writer.stream_position()?.div_ceil(GROUP_DATA_SIZE);
+            (groups * GROUP_DATA_SIZE, groups * GROUP_SIZE)
+        };
 
         self.current_data_offset += 0x20000 /* encrypted data off */ + total_encrypted_size;
 
@@ -248,7 +842,7 @@ impl<WS: Read + Write + Seek> WiiDiscBuilder<WS> {
         crypto_writer.seek(SeekFrom::Start(0))?;
         crypto_writer.write_be(&part_disc_header)?;
         crypto_writer.flush()?;
-        let h3 = crypto_writer.take_h3().unwrap();
+        let h3 = crypto_writer.take_h3();
         // we're done with the encrypted part, only need to correct some headers now
         drop(crypto_writer);
         // write h3
@@ -260,31 +854,14 @@ impl<WS: Read + Write + Seek> WiiDiscBuilder<WS> {
         part_header.data_size = total_size.into();
 
         // fix tmd, see: https://github.com/AxioDL/nod/blob/b513a7f4e02d1b2a0c4563af73ba261d6760ab0e/lib/DiscWii.cpp#L885
-        let mut hasher = Sha1::new();
-        hasher.update(h3.as_ref());
-        let digest = hasher.finalize_reset();
-        // replace content hash
-        tmd_buf[0x1F4..][..20].copy_from_slice(&digest);
-        // replace content size
-        tmd_buf[0x1EC..][..8].copy_from_slice(&total_size.to_be_bytes());
-        // zero out TMD for simpler brute force
-        for b in tmd_buf.iter_mut().skip(4).take(0x100) {
-            *b = 0;
-        }
-
-        hasher.reset();
-        // brute force 0 starting hash
-        for i in 0..u64::MAX {
-            tmd_buf[0x19A..][..8].copy_from_slice(&i.to_ne_bytes());
-            hasher.update(&tmd_buf[0x140..]);
-            let hash = hasher.finalize_reset();
-            if hash[0] == 0 {
-                break;
-            }
+        if let Some(content) = tmd.contents.first_mut() {
+            content.hash.copy_from_slice(&Sha1::digest(h3.as_ref()));
+            content.size = total_size;
         }
+        fakesign_tmd(&mut tmd)?;
 
         partition_window.seek(SeekFrom::Start(*part_header.tmd_off))?;
-        partition_window.write_all(&tmd_buf)?;
+        partition_window.write_be(&tmd)?;
 
         // write partition header
         partition_window.seek(SeekFrom::Start(0))?;
@@ -293,12 +870,21 @@ impl<WS: Read + Write + Seek> WiiDiscBuilder<WS> {
     }
 
     pub fn finish(&mut self) -> binrw::BinResult<()> {
+        if let Some(title_override) = &self.title_override {
+            title_override.apply_to_header(&mut self.disc_header);
+        }
+        if let Some(region_free) = &self.region_free {
+            region_free.apply_to_header(&mut self.disc_header);
+            self.region.set_region_free();
+        }
+        self.disc_header
+            .set_partition_data_mode(self.partition_data_mode);
         // disc header
         self.file.seek(SeekFrom::Start(0))?;
         self.file.write_be(&self.disc_header)?;
         // region info
         self.file.seek(SeekFrom::Start(0x4E000))?;
-        self.file.write_all(&self.region)?;
+        self.file.write_be(&self.region)?;
         // partition info
         self.file.seek(SeekFrom::Start(0x40000))?;
         // we keep everything in one group, first write count then offset
@@ -314,22 +900,145 @@ impl<WS: Read + Write + Seek> WiiDiscBuilder<WS> {
     }
 }
 
-struct CopyBuilder<'a, RS: Read + Seek> {
+#[derive(thiserror::Error, Debug)]
+#[error("can't place a file at {0}")]
+pub struct InvalidFilePath(pub String);
+
+/// Partition definition that copies a partition of an existing ISO,
+/// files and the DOL can be replaced and new files can be added
+pub struct IsoPartitionBuilder<'a, RS: Read + Seek> {
     reader: &'a mut WiiIsoReader<RS>,
     part_read_info: WiiPartitionReadInfo,
-    bi2: Vec<u8>,
+    bi2: Bi2,
     buffer: Vec<u8>,
-    original_fst: Fst,
+    fst: Fst,
+    /// data of replaced and added files, by full path
+    file_overrides: HashMap<String, Vec<u8>>,
+    dol_override: Option<Vec<u8>>,
+}
+
+fn path_key<'a>(iter: impl Iterator<Item = &'a str>) -> String {
+    iter.filter(|p| !p.is_empty()).collect::<Vec<_>>().join("/")
+}
+
+impl<'a, RS: Read + Seek> IsoPartitionBuilder<'a, RS> {
+    pub fn new(
+        reader: &'a mut WiiIsoReader<RS>,
+        mut part_read_info: WiiPartitionReadInfo,
+    ) -> binrw::BinResult<Self> {
+        let bi2 = part_read_info.read_parsed_bi2(reader)?;
+        let fst = part_read_info.get_fst().clone();
+        Ok(Self {
+            reader,
+            part_read_info,
+            bi2,
+            buffer: Vec::new(),
+            fst,
+            file_overrides: HashMap::new(),
+            dol_override: None,
+        })
+    }
+
+    pub fn get_partition_read_info(&self) -> &WiiPartitionReadInfo {
+        &self.part_read_info
+    }
+
+    /// The bi2 that will be built, for example to change the region code
+    pub fn get_current_bi2(&self) -> &Bi2 {
+        &self.bi2
+    }
+
+    pub fn get_current_bi2_mut(&mut self) -> &mut Bi2 {
+        &mut self.bi2
+    }
+
+    /// The file system table that will be built, removing nodes here removes them from
+    /// the output. Files added here need to have their data set with [`Self::set_file`]
+    pub fn get_current_fst(&self) -> &Fst {
+        &self.fst
+    }
+
+    pub fn get_current_fst_mut(&mut self) -> &mut Fst {
+        &mut self.fst
+    }
+
+    /// Replaces the data of a file or adds a new file, fails if the path is a directory
+    /// or leads through a file
+    pub fn set_file(&mut self, path: &str, data: Vec<u8>) -> Result<(), InvalidFilePath> {
+        let invalid_path = || InvalidFilePath(path.to_string());
+        match self.fst.find_node_path_mut(path) {
+            Some(FstNode::File { length, .. }) => *length = data.len() as u32,
+            Some(FstNode::Directory { .. }) => return Err(invalid_path()),
+            None => {
+                let mut parts: Vec<&str> = path.split('/').filter(|p| !p.is_empty()).collect();
+                let name = parts.pop().ok_or_else(invalid_path)?;
+                let node = FstNode::File {
+                    name: name.to_string(),
+                    offset: 0,
+                    length: data.len() as u32,
+                };
+                self.fst
+                    .add_node_iter(parts.into_iter(), node)
+                    .map_err(|_| invalid_path())?;
+            }
+        }
+        self.file_overrides.insert(path_key(path.split('/')), data);
+        Ok(())
+    }
+
+    /// Removes a file or directory from the output
+    pub fn remove_node(&mut self, path: &str) -> Option<FstNode> {
+        let node = self.fst.remove_node_path(path).ok()?;
+        let key = path_key(path.split('/'));
+        let prefix = format!("{key}/");
+        self.file_overrides
+            .retain(|path, _| *path != key && !path.starts_with(&prefix));
+        Some(node)
+    }
+
+    /// Reads the current data of a file, either the replaced data or from the ISO
+    pub fn read_file(&mut self, path: &str) -> Option<io::Result<Vec<u8>>> {
+        if let Some(data) = self.file_overrides.get(&path_key(path.split('/'))) {
+            return Some(Ok(data.clone()));
+        }
+        let (offset, length) = match self.fst.find_node_path(path)? {
+            FstNode::File { offset, length, .. } => (*offset, *length),
+            FstNode::Directory { .. } => return None,
+        };
+        let mut buf = Vec::new();
+        Some(
+            self.part_read_info
+                .get_crypto_reader(self.reader)
+                .read_into_vec(offset, length as u64, &mut buf)
+                .map(|_| buf),
+        )
+    }
+
+    pub fn set_dol(&mut self, dol: Vec<u8>) {
+        self.dol_override = Some(dol);
+    }
+
+    /// Reads the current DOL, either the replaced one or from the ISO
+    pub fn read_dol(&mut self) -> binrw::BinResult<Vec<u8>> {
+        match &self.dol_override {
+            Some(dol) => Ok(dol.clone()),
+            None => self.part_read_info.read_dol(self.reader),
+        }
+    }
 }
 
 type CpBuildErr = PartitionAddError<std::convert::Infallible>;
-impl<'b, RS: Read + Seek> WiiPartitionDefinition<std::convert::Infallible> for CopyBuilder<'b, RS> {
+impl<'b, RS: Read + Seek> WiiPartitionDefinition<std::convert::Infallible>
+    for IsoPartitionBuilder<'b, RS>
+{
     fn get_disc_header(&mut self) -> Result<DiscHeader, CpBuildErr> {
         Ok(self.part_read_info.get_encrypted_header().clone())
     }
 
     fn get_bi2<'a>(&'a mut self) -> Result<Cow<'a, [u8]>, CpBuildErr> {
-        Ok(Cow::Borrowed(&self.bi2))
+        self.buffer.clear();
+        Cursor::new(&mut self.buffer).write_be(&self.bi2)?;
+        Ok(Cow::Borrowed(&self.buffer))
     }
 
     fn get_apploader<'a>(&'a mut self) -> Result<Cow<'a, [u8]>, CpBuildErr> {
@@ -337,29 +1046,49 @@ impl<'b, RS: Read + Seek> WiiPartitionDefinition<std::convert::Infallible> for C
     }
 
     fn get_fst(&mut self) -> Result<Fst, CpBuildErr> {
-        Ok(self.original_fst.clone())
+        Ok(self.fst.clone())
     }
 
     fn get_dol<'a>(&'a mut self) -> Result<Cow<'a, [u8]>, CpBuildErr> {
-        Ok(self.part_read_info.read_dol(self.reader)?.into())
+        match &self.dol_override {
+            Some(dol) => Ok(Cow::Borrowed(dol)),
+            None => Ok(self.part_read_info.read_dol(self.reader)?.into()),
+        }
     }
 
     fn get_file_data<'a>(
         &'a mut self,
         path: &Vec<String>,
     ) -> Result<(Cow<'a, [u8]>, u32), CpBuildErr> {
-        match self
-            .original_fst
-            .find_node_iter(path.iter().map(Borrow::borrow))
+        if let Some(data) = self
+            .file_overrides
+            .get(&path_key(path.iter().map(String::as_str)))
         {
+            return Ok((Cow::Borrowed(data), 0));
+        }
+        match self.fst.find_node_iter(path.iter().map(Borrow::borrow)) {
             Some(FstNode::File { offset, length, .. }) => {
-                println!("copying {:?}, {}", path, offset);
                 self.part_read_info
                     .get_crypto_reader(self.reader)
                     .read_into_vec(*offset, *length as u64, &mut self.buffer)?;
                 Ok((Cow::Borrowed(&self.buffer), 0))
             }
-            _ => panic!("???"),
+            _ => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no data for {}", path.join("/")),
+            )
+            .into()),
+        }
+    }
+
+    fn get_original_location(&mut self, path: &[String]) -> Option<(u64, u32)> {
+        match self
+            .part_read_info
+            .get_fst()
+            .find_node_iter(path.iter().map(String::as_str))?
+        {
+            FstNode::File { offset, length, .. } => Some((*offset, *length)),
+            FstNode::Directory { .. } => None,
         }
     }
 }
@@ -374,7 +1103,7 @@ pub fn build_copy(src: &Path, dest: &Path) -> Result<(), CpBuildErr> {
             .write(true)
             .open(dest)?,
         reader.get_header().clone(),
-        *reader.get_region(),
+        reader.get_region().clone(),
     );
     let data_part = reader
         .partitions()
@@ -386,22 +1115,17 @@ pub fn build_copy(src: &Path, dest: &Path) -> Result<(), CpBuildErr> {
     let ticket = part_read_info.get_partition_header().ticket.clone();
     let tmd = part_read_info.read_tmd(&mut reader)?;
     let cert_chain = part_read_info.read_certificates(&mut reader)?;
-    let bi2 = part_read_info.read_bi2(&mut reader)?;
-    let mut original_fst = part_read_info.get_fst().clone();
-    let thp_dir = original_fst.find_node_path_mut("THP").unwrap();
+    let mut copy_builder = IsoPartitionBuilder::new(&mut reader, part_read_info)?;
+    let thp_dir = copy_builder
+        .get_current_fst_mut()
+        .find_node_path_mut("THP")
+        .unwrap();
     match thp_dir {
         FstNode::File { .. } => unreachable!(),
         FstNode::Directory { files, .. } => {
             files.retain(|f| f.get_name().starts_with("Demo"));
         }
     }
-    let mut copy_builder = CopyBuilder {
-        bi2,
-        original_fst,
-        buffer: Vec::new(),
-        part_read_info,
-        reader: &mut reader,
-    };
     builder.add_partition(
         WiiPartType::Data,
         ticket,
@@ -485,20 +1209,28 @@ pub fn build_from_directory<WS: Write + Seek + Read, C: FnMut(u8)>(
     dest: &mut WS,
     progress_cb: &mut C,
 ) -> Result<(), DirPartAddErr> {
-    let mut disc_header = {
+    build_from_directory_with_mode(dir, dest, PartitionDataMode::Encrypted, progress_cb)
+}
+
+/// builds a disc from an extracted directory, storing the partition data as specified
+pub fn build_from_directory_with_mode<WS: Write + Seek + Read, C: FnMut(u8)>(
+    dir: &Path,
+    dest: &mut WS,
+    data_mode: PartitionDataMode,
+    progress_cb: &mut C,
+) -> Result<(), DirPartAddErr> {
+    let disc_header = {
         let path = dir.join("DATA/sys/boot.bin");
         try_open(path)?.read_be::<DiscHeader>()?
     };
-    disc_header.disable_disc_enc = 0;
-    disc_header.disable_hash_verification = 0;
     let region = {
         let path = dir.join("DATA/disc/region.bin");
-        let mut f = try_open(path)?;
-        let mut region = [0; 32];
-        f.read_exact(&mut region)?;
-        region
+        try_open(path)?.read_be::<Region>()?
     };
     let mut builder = WiiDiscBuilder::create(dest, disc_header, region);
+    builder
+        .set_partition_data_mode(data_mode)
+        .expect("no partition was added yet");
     let partition_path = dir.join("DATA");
     let ticket = {
         let path = partition_path.join("ticket.bin");
@@ -534,3 +1266,412 @@ pub fn build_from_directory<WS: Write + Seek + Read, C: FnMut(u8)>(
     builder.finish()?;
     Ok(())
 }
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::{
+        borrow::Cow,
+        collections::HashMap,
+        convert::Infallible,
+        io::{Cursor, Read},
+    };
+
+    use binrw::{BinReaderExt, BinWriterExt};
+
+    use super::{
+        IsoPartitionBuilder, PartitionAddError, RegionFree, TitleOverride, WiiDiscBuilder,
+        WiiPartitionDefinition,
+    };
+    use crate::{
+        layout::{LayoutError, LayoutPolicy},
+        structs::{
+            Certificate, DiscHeader, KeyType, PartitionDataMode, SigType, Ticket, WiiPartType, TMD,
+        },
+        Fst, FstNode, WiiIsoReader,
+    };
+
+    type TestErr = PartitionAddError<Infallible>;
+
+    /// Partition with files in memory, optionally based on a partition with the
+    /// given file locations
+    pub(crate) struct TestPartition {
+        pub(crate) files: Vec<(&'static str, Vec<u8>)>,
+        pub(crate) original: HashMap<&'static str, (u64, u32)>,
+    }
+
+    impl TestPartition {
+        pub(crate) fn new(files: Vec<(&'static str, Vec<u8>)>) -> Self {
+            Self {
+                files,
+                original: HashMap::new(),
+            }
+        }
+    }
+
+    fn test_disc_header() -> DiscHeader {
+        let mut buf = vec![0u8; 0x440];
+        buf[..6].copy_from_slice(b"RTST01");
+        // wii magic
+        buf[0x18..0x1C].copy_from_slice(&0x5D1C9EA3u32.to_be_bytes());
+        buf[0x20..0x24].copy_from_slice(b"Test");
+        Cursor::new(buf).read_be().unwrap()
+    }
+
+    fn test_ticket() -> Ticket {
+        let mut buf = vec![0u8; 0x2A4];
+        buf[..4].copy_from_slice(&0x10001u32.to_be_bytes());
+        buf[0x1DC..0x1E4].copy_from_slice(b"\0\x01\0\0RTST");
+        Cursor::new(buf).read_be().unwrap()
+    }
+
+    fn test_tmd() -> TMD {
+        let mut buf = vec![0u8; 0x1E4 + 0x24];
+        buf[..4].copy_from_slice(&0x10001u32.to_be_bytes());
+        // one content
+        buf[0x1DE..0x1E0].copy_from_slice(&1u16.to_be_bytes());
+        Cursor::new(buf).read_be().unwrap()
+    }
+
+    fn test_certificates() -> [Certificate; 3] {
+        [(); 3].map(|_| Certificate {
+            sig_type: SigType::Rsa2048,
+            sig: vec![0; 256],
+            issuer: [0; 64],
+            key_type: KeyType::Rsa2048,
+            subject: [0; 64],
+            key_id: 0,
+            key: vec![0; 256],
+            pub_exp: 0,
+        })
+    }
+
+    impl WiiPartitionDefinition<Infallible> for TestPartition {
+        fn get_disc_header(&mut self) -> Result<DiscHeader, TestErr> {
+            Ok(test_disc_header())
+        }
+
+        fn get_bi2<'a>(&'a mut self) -> Result<Cow<'a, [u8]>, TestErr> {
+            Ok(vec![0; 0x2000].into())
+        }
+
+        fn get_apploader<'a>(&'a mut self) -> Result<Cow<'a, [u8]>, TestErr> {
+            let mut apploader = vec![0u8; 0x20 + 0x40];
+            apploader[..10].copy_from_slice(b"2008/01/01");
+            // size1
+            apploader[0x14..0x18].copy_from_slice(&0x40u32.to_be_bytes());
+            Ok(apploader.into())
+        }
+
+        fn get_fst(&mut self) -> Result<Fst, TestErr> {
+            let mut fst = Fst::new();
+            for (path, data) in &self.files {
+                let mut parts: Vec<&str> = path.split('/').collect();
+                let name = parts.pop().unwrap();
+                let node = FstNode::File {
+                    name: name.to_string(),
+                    offset: 0,
+                    length: data.len() as u32,
+                };
+                fst.add_node_iter(parts.into_iter(), node).unwrap();
+            }
+            Ok(fst)
+        }
+
+        fn get_dol<'a>(&'a mut self) -> Result<Cow<'a, [u8]>, TestErr> {
+            Ok(vec![0; 0x100].into())
+        }
+
+        fn get_file_data<'a>(
+            &'a mut self,
+            path: &Vec<String>,
+        ) -> Result<(Cow<'a, [u8]>, u32), TestErr> {
+            let path = path.join("/");
+            let (_, data) = self.files.iter().find(|(p, _)| *p == path).unwrap();
+            Ok((Cow::Borrowed(data), 0))
+        }
+
+        fn get_original_location(&mut self, path: &[String]) -> Option<(u64, u32)> {
+            self.original.get(path.join("/").as_str()).copied()
+        }
+    }
+
+    pub(crate) fn build(
+        partition: &mut TestPartition,
+        configure: impl FnOnce(&mut WiiDiscBuilder<&mut Cursor<Vec<u8>>>),
+    ) -> Result<Vec<u8>, TestErr> {
+        let mut out = Cursor::new(Vec::new());
+        let region = Cursor::new([0u8; 0x20]).read_be()?;
+        let mut builder = WiiDiscBuilder::create(&mut out, test_disc_header(), region);
+        builder
+            .set_partition_data_mode(PartitionDataMode::Plain)
+            .unwrap();
+        configure(&mut builder);
+        builder.add_partition(
+            WiiPartType::Data,
+            test_ticket(),
+            test_tmd(),
+            test_certificates(),
+            partition,
+            &mut |_| {},
+        )?;
+        builder.finish()?;
+        Ok(out.into_inner())
+    }
+
+    /// offset and length of all files and the data size of the first partition
+    fn read_layout(disc: Vec<u8>) -> (HashMap<String, (u64, u32)>, u64) {
+        let mut reader = WiiIsoReader::open(Cursor::new(disc)).unwrap();
+        let partition = reader.partitions()[0].clone();
+        let info = reader.open_partition(partition).unwrap();
+        let files = info
+            .get_fst()
+            .files()
+            .map(|entry| match entry.node {
+                FstNode::File { offset, length, .. } => (entry.path, (*offset, *length)),
+                FstNode::Directory { .. } => unreachable!(),
+            })
+            .collect();
+        (files, info.get_data_size())
+    }
+
+    pub(crate) fn read_file(disc: Vec<u8>, path: &str) -> Vec<u8> {
+        let mut reader = WiiIsoReader::open(Cursor::new(disc)).unwrap();
+        let partition = reader.partitions()[0].clone();
+        let mut info = reader.open_partition(partition).unwrap();
+        let mut buf = Vec::new();
+        info.open_file(&mut reader, path)
+            .unwrap()
+            .read_to_end(&mut buf)
+            .unwrap();
+        buf
+    }
+
+    #[test]
+    pub fn test_layout_policy() {
+        let mut partition = TestPartition::new(vec![
+            ("a.bin", vec![1; 100]),
+            ("dir/b.bin", vec![2; 0x1000]),
+            ("dir/c.bin", b"hello world".to_vec()),
+        ]);
+        let disc = build(&mut partition, |builder| {
+            let mut policy = LayoutPolicy::new();
+            policy.add_first("dir/c.bin");
+            policy.add_last("a.bin");
+            policy.add_alignment("dir/**", 0x8000).unwrap();
+            policy.add_slack("a.bin", 0x10000);
+            builder.set_layout_policy(policy);
+        })
+        .unwrap();
+        assert_eq!(read_file(disc.clone(), "dir/c.bin"), b"hello world");
+        let (files, data_size) = read_layout(disc);
+        let (a_offset, _) = files["a.bin"];
+        let (b_offset, _) = files["dir/b.bin"];
+        let (c_offset, c_length) = files["dir/c.bin"];
+        assert_eq!(c_offset % 0x8000, 0);
+        assert_eq!(b_offset, c_offset + 0x8000);
+        assert_eq!(a_offset, b_offset + 0x1000);
+        assert_eq!(c_length, 11);
+        // slack after the last file is part of the partition
+        assert!(data_size >= a_offset + 100 + 0x10000);
+    }
+
+    #[test]
+    pub fn test_pinned_files() {
+        let files = || {
+            vec![
+                ("a.bin", vec![1; 100]),
+                ("dir/b.bin", vec![2; 0x1000]),
+                ("dir/c.bin", vec![3; 0x20]),
+                ("empty", Vec::new()),
+            ]
+        };
+        fn pin(builder: &mut WiiDiscBuilder<&mut Cursor<Vec<u8>>>) {
+            let mut policy = LayoutPolicy::new();
+            policy.add_pin_original("dir/*");
+            policy.add_pin_original("empty");
+            builder.set_layout_policy(policy);
+        }
+        let mut partition = TestPartition::new(files());
+        partition.original.insert("dir/b.bin", (0x100000, 0x1000));
+        partition.original.insert("dir/c.bin", (0x80000, 0x40));
+        // empty files can share the offset of other pinned files
+        partition.original.insert("empty", (0x80000, 0));
+        let disc = build(&mut partition, pin).unwrap();
+        assert_eq!(read_file(disc.clone(), "dir/b.bin"), vec![2; 0x1000]);
+        let (layout, _) = read_layout(disc);
+        assert_eq!(layout["dir/b.bin"], (0x100000, 0x1000));
+        assert_eq!(layout["dir/c.bin"], (0x80000, 0x20));
+        assert_eq!(layout["empty"], (0x80000, 0));
+        assert!(layout["a.bin"].0 < 0x80000);
+
+        let mut partition = TestPartition::new(files());
+        partition.original.insert("dir/b.bin", (0x100000, 0x800));
+        assert!(matches!(
+            build(&mut partition, pin),
+            Err(PartitionAddError::Layout(LayoutError::PinnedFileGrew { path, .. }))
+                if path == "dir/b.bin"
+        ));
+
+        let mut partition = TestPartition::new(files());
+        partition.original.insert("dir/b.bin", (0x100000, 0x1000));
+        partition.original.insert("dir/c.bin", (0x100800, 0x20));
+        assert!(matches!(
+            build(&mut partition, pin),
+            Err(PartitionAddError::Layout(LayoutError::PinnedFileOverlap { path, .. }))
+                if path == "dir/c.bin"
+        ));
+    }
+
+    #[test]
+    pub fn test_preserve_offsets_copy() {
+        let mut partition = TestPartition::new(vec![
+            ("a.bin", vec![1; 0x1000]),
+            ("b.bin", vec![2; 0x200]),
+            ("copy.bin", vec![1; 0x1000]),
+            ("dir/empty", Vec::new()),
+            ("dir/z.bin", vec![3; 0x30]),
+        ]);
+        // deduplicating gives a source with shared data
+        let source = build(&mut partition, |builder| {
+            builder.set_deduplicate_files(true)
+        })
+        .unwrap();
+        let (source_layout, _) = read_layout(source.clone());
+        assert_eq!(source_layout["a.bin"], source_layout["copy.bin"]);
+
+        let mut reader = WiiIsoReader::open(Cursor::new(source)).unwrap();
+        let partition = reader.partitions()[0].clone();
+        let mut info = reader.open_partition(partition).unwrap();
+        let ticket = info.get_partition_header().ticket.clone();
+        let tmd = info.read_tmd(&mut reader).unwrap();
+        let certificates = info.read_certificates(&mut reader).unwrap();
+        let mut copy = IsoPartitionBuilder::new(&mut reader, info).unwrap();
+        let mut out = Cursor::new(Vec::new());
+        let region = Cursor::new([0u8; 0x20]).read_be().unwrap();
+        let mut builder = WiiDiscBuilder::create(&mut out, test_disc_header(), region);
+        builder
+            .set_partition_data_mode(PartitionDataMode::Plain)
+            .unwrap();
+        let mut policy = LayoutPolicy::new();
+        policy.set_preserve_offsets(true);
+        builder.set_layout_policy(policy);
+        builder
+            .add_partition(
+                WiiPartType::Data,
+                ticket,
+                tmd,
+                certificates,
+                &mut copy,
+                &mut |_| {},
+            )
+            .unwrap();
+        builder.finish().unwrap();
+        let disc = out.into_inner();
+        assert_eq!(read_file(disc.clone(), "copy.bin"), vec![1; 0x1000]);
+        let (layout, _) = read_layout(disc);
+        assert_eq!(layout, source_layout);
+    }
+
+    #[test]
+    pub fn test_deduplicate_files() {
+        let files = || {
+            vec![
+                ("a.bin", vec![1; 0x40000]),
+                ("dir/b.bin", vec![1; 0x40000]),
+                ("dir/c.bin", vec![2; 0x40000]),
+            ]
+        };
+        let plain_disc = build(&mut TestPartition::new(files()), |_| {}).unwrap();
+        let dedup_disc = build(&mut TestPartition::new(files()), |builder| {
+            builder.set_deduplicate_files(true)
+        })
+        .unwrap();
+        assert_eq!(read_file(dedup_disc.clone(), "dir/b.bin"), vec![1; 0x40000]);
+        assert_eq!(read_file(dedup_disc.clone(), "dir/c.bin"), vec![2; 0x40000]);
+        let (plain_layout, plain_size) = read_layout(plain_disc);
+        let (layout, size) = read_layout(dedup_disc);
+        assert_ne!(plain_layout["a.bin"], plain_layout["dir/b.bin"]);
+        assert_eq!(layout["a.bin"], layout["dir/b.bin"]);
+        assert_ne!(layout["a.bin"].0, layout["dir/c.bin"].0);
+        assert!(size < plain_size);
+    }
+
+    #[test]
+    pub fn test_partition_data_mode() {
+        let mut out = Cursor::new(Vec::new());
+        let region = Cursor::new([0u8; 0x20]).read_be().unwrap();
+        let mut builder = WiiDiscBuilder::create(&mut out, test_disc_header(), region);
+        builder
+            .set_partition_data_mode(PartitionDataMode::Unencrypted)
+            .unwrap();
+        let mut partition = TestPartition::new(vec![("a.bin", vec![1; 0x100])]);
+        builder
+            .add_partition(
+                WiiPartType::Data,
+                test_ticket(),
+                test_tmd(),
+                test_certificates(),
+                &mut partition,
+                &mut |_| {},
+            )
+            .unwrap();
+        // the mode is stored in the header of the whole disc
+        assert!(builder
+            .set_partition_data_mode(PartitionDataMode::Plain)
+            .is_err());
+        builder
+            .set_partition_data_mode(PartitionDataMode::Unencrypted)
+            .unwrap();
+        // same for the settings that change the disc header
+        assert!(builder
+            .set_title_override(Some(TitleOverride::new("RZZE99", None).unwrap()))
+            .is_err());
+        assert!(builder
+            .set_region_free(Some(RegionFree::new(None).unwrap()))
+            .is_err());
+        builder.set_title_override(None).unwrap();
+        builder.finish().unwrap();
+        let disc = out.into_inner();
+        assert_eq!(read_file(disc.clone(), "a.bin"), vec![1; 0x100]);
+        let reader = WiiIsoReader::open(Cursor::new(disc)).unwrap();
+        assert_eq!(
+            reader.get_header().get_partition_data_mode(),
+            PartitionDataMode::Unencrypted
+        );
+    }
+
+    #[test]
+    pub fn test_title_override() {
+        assert!(TitleOverride::new("RTST0", None).is_err());
+        assert!(TitleOverride::new("rtst01", None).is_err());
+        assert!(TitleOverride::new("RTST01", Some("x".repeat(64))).is_err());
+        let title_override = TitleOverride::new("RZZE99", Some("Mod".to_string())).unwrap();
+
+        let mut header = test_disc_header();
+        title_override.apply_to_header(&mut header);
+        assert_eq!(&header.game_id, b"RZZE99");
+        assert_eq!(header.game_title, "Mod");
+
+        let mut ticket = test_ticket();
+        ticket.title_key = [7; 16];
+        let mut tmd = test_tmd();
+        title_override.apply_to_title(&mut ticket, &mut tmd);
+        assert_eq!(&ticket.title_id, b"\0\x01\0\0RZZE");
+        assert_eq!(&tmd.title_id_minor, b"RZZE");
+
+        // the title key is encrypted with the new title ID as IV
+        let mut buf = Vec::new();
+        Cursor::new(&mut buf).write_be(&ticket).unwrap();
+        assert_eq!(&buf[0x1DC..0x1E4], b"\0\x01\0\0RZZE");
+        let mut original_ticket = test_ticket();
+        original_ticket.title_key = [7; 16];
+        let mut original_buf = Vec::new();
+        Cursor::new(&mut original_buf)
+            .write_be(&original_ticket)
+            .unwrap();
+        assert_ne!(buf[0x1BF..0x1CF], original_buf[0x1BF..0x1CF]);
+        let read_ticket: Ticket = Cursor::new(&buf).read_be().unwrap();
+        assert_eq!(read_ticket.title_id, ticket.title_id);
+        assert_eq!(read_ticket.title_key, [7; 16]);
+    }
+}
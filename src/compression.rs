@@ -0,0 +1,462 @@
+use std::io;
+
+use thiserror::Error;
+
+pub const YAZ0_MAGIC: &[u8; 4] = b"Yaz0";
+pub const YAY0_MAGIC: &[u8; 4] = b"Yay0";
+pub const LZ77_MAGIC: &[u8; 4] = b"LZ77";
+
+/// largest distance a back reference can have
+pub const MAX_SEARCH_WINDOW: usize = 0x1000;
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 0xFF + 0x12;
+// how many candidates are checked for each position, limits the time spent on
+// highly repetitive data
+const MAX_CHAIN: usize = 256;
+const HASH_BITS: u32 = 15;
+const NO_POS: u32 = u32::MAX;
+
+#[derive(Error, Debug)]
+pub enum CompressionError {
+    #[error("io error: {0}")]
+    IO(#[from] io::Error),
+    #[error("invalid magic: {0:?}")]
+    InvalidMagic([u8; 4]),
+    #[error("compressed data ended unexpectedly")]
+    Truncated,
+    #[error("back reference at {0:#x} points before the start of the data")]
+    InvalidBackReference(usize),
+    #[error("LZ77 compression type {0:#x} is not supported")]
+    UnsupportedLz77Type(u8),
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, CompressionError> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+        .ok_or(CompressionError::Truncated)
+}
+
+fn read_u8(data: &[u8], offset: usize) -> Result<u8, CompressionError> {
+    data.get(offset).copied().ok_or(CompressionError::Truncated)
+}
+
+/// checks the magic and returns the decompressed size
+fn read_header(data: &[u8], magic: &[u8; 4]) -> Result<usize, CompressionError> {
+    let actual_magic: [u8; 4] = data
+        .get(..4)
+        .ok_or(CompressionError::Truncated)?
+        .try_into()
+        .unwrap();
+    if &actual_magic != magic {
+        return Err(CompressionError::InvalidMagic(actual_magic));
+    }
+    Ok(read_u32(data, 4)? as usize)
+}
+
+/// buffer for the decompressed data, the size comes from the header, so only as much is
+/// preallocated as the input could plausibly expand to
+fn output_buffer(size: usize, input_len: usize) -> Vec<u8> {
+    Vec::with_capacity(size.min(input_len.saturating_mul(9)))
+}
+
+fn copy_back_reference(
+    out: &mut Vec<u8>,
+    dist: usize,
+    length: usize,
+    src_pos: usize,
+) -> Result<(), CompressionError> {
+    if dist > out.len() {
+        return Err(CompressionError::InvalidBackReference(src_pos));
+    }
+    // source and destination can overlap, so copy byte by byte
+    let start = out.len() - dist;
+    for i in start..start + length {
+        out.push(out[i]);
+    }
+    Ok(())
+}
+
+/// Returns true if the data starts with the Yaz0 or Yay0 magic
+pub fn is_compressed(data: &[u8]) -> bool {
+    data.starts_with(YAZ0_MAGIC) || data.starts_with(YAY0_MAGIC)
+}
+
+/// Decompresses Yaz0 or Yay0 data, data without either magic is returned unchanged
+pub fn decompress_if_compressed(data: Vec<u8>) -> Result<Vec<u8>, CompressionError> {
+    if data.starts_with(YAZ0_MAGIC) {
+        decompress_yaz0(&data)
+    } else if data.starts_with(YAY0_MAGIC) {
+        decompress_yay0(&data)
+    } else {
+        Ok(data)
+    }
+}
+
+pub fn decompress_yaz0(data: &[u8]) -> Result<Vec<u8>, CompressionError> {
+    let size = read_header(data, YAZ0_MAGIC)?;
+    let mut out = output_buffer(size, data.len());
+    let mut src_pos = 0x10;
+    let mut group_header = 0u8;
+    let mut bits_left = 0;
+    while out.len() < size {
+        if bits_left == 0 {
+            group_header = read_u8(data, src_pos)?;
+            src_pos += 1;
+            bits_left = 8;
+        }
+        if group_header & 0x80 != 0 {
+            out.push(read_u8(data, src_pos)?);
+            src_pos += 1;
+        } else {
+            let b1 = read_u8(data, src_pos)? as usize;
+            let b2 = read_u8(data, src_pos + 1)? as usize;
+            let dist = ((b1 & 0xF) << 8 | b2) + 1;
+            let length = match b1 >> 4 {
+                0 => {
+                    let length = read_u8(data, src_pos + 2)? as usize + 0x12;
+                    src_pos += 1;
+                    length
+                }
+                n => n + 2,
+            };
+            let length = length.min(size - out.len());
+            copy_back_reference(&mut out, dist, length, src_pos)?;
+            src_pos += 2;
+        }
+        group_header <<= 1;
+        bits_left -= 1;
+    }
+    Ok(out)
+}
+
+/// Decompresses LZ77 data with the "LZ77" magic, as used in banners and channels.
+/// Only the LZ10 variant is supported
+pub fn decompress_lz77(data: &[u8]) -> Result<Vec<u8>, CompressionError> {
+    let info = read_header(data, LZ77_MAGIC)?;
+    // the size info is little endian
+    let info = (info as u32).swap_bytes();
+    if info as u8 != 0x10 {
+        return Err(CompressionError::UnsupportedLz77Type(info as u8));
+    }
+    let size = (info >> 8) as usize;
+    let mut out = output_buffer(size, data.len());
+    let mut src_pos = 8;
+    let mut flags = 0u8;
+    let mut bits_left = 0;
+    while out.len() < size {
+        if bits_left == 0 {
+            flags = read_u8(data, src_pos)?;
+            src_pos += 1;
+            bits_left = 8;
+        }
+        if flags & 0x80 == 0 {
+            out.push(read_u8(data, src_pos)?);
+            src_pos += 1;
+        } else {
+            let b1 = read_u8(data, src_pos)? as usize;
+            let b2 = read_u8(data, src_pos + 1)? as usize;
+            let dist = ((b1 & 0xF) << 8 | b2) + 1;
+            let length = ((b1 >> 4) + 3).min(size - out.len());
+            copy_back_reference(&mut out, dist, length, src_pos)?;
+            src_pos += 2;
+        }
+        flags <<= 1;
+        bits_left -= 1;
+    }
+    Ok(out)
+}
+
+pub fn decompress_yay0(data: &[u8]) -> Result<Vec<u8>, CompressionError> {
+    let size = read_header(data, YAY0_MAGIC)?;
+    let mut link_pos = read_u32(data, 8)? as usize;
+    let mut chunk_pos = read_u32(data, 12)? as usize;
+    let mut mask_pos = 0x10;
+    let mut out = output_buffer(size, data.len());
+    let mut mask = 0u32;
+    let mut bits_left = 0;
+    while out.len() < size {
+        if bits_left == 0 {
+            mask = read_u32(data, mask_pos)?;
+            mask_pos += 4;
+            bits_left = 32;
+        }
+        if mask & 0x8000_0000 != 0 {
+            out.push(read_u8(data, chunk_pos)?);
+            chunk_pos += 1;
+        } else {
+            let link =
+                (read_u8(data, link_pos)? as usize) << 8 | read_u8(data, link_pos + 1)? as usize;
+            let dist = (link & 0xFFF) + 1;
+            let length = match link >> 12 {
+                0 => {
+                    let length = read_u8(data, chunk_pos)? as usize + 0x12;
+                    chunk_pos += 1;
+                    length
+                }
+                n => n + 2,
+            };
+            let length = length.min(size - out.len());
+            copy_back_reference(&mut out, dist, length, link_pos)?;
+            link_pos += 2;
+        }
+        mask <<= 1;
+        bits_left -= 1;
+    }
+    Ok(out)
+}
+
+enum Token {
+    Literal(u8),
+    BackReference { dist: usize, length: usize },
+}
+
+/// finds matches using hash chains over the first 3 bytes
+struct MatchFinder<'a> {
+    data: &'a [u8],
+    search_window: usize,
+    head: Vec<u32>,
+    prev: Vec<u32>,
+}
+
+impl<'a> MatchFinder<'a> {
+    fn new(data: &'a [u8], search_window: usize) -> Self {
+        Self {
+            data,
+            search_window: search_window.clamp(1, MAX_SEARCH_WINDOW),
+            head: vec![NO_POS; 1 << HASH_BITS],
+            prev: vec![NO_POS; data.len()],
+        }
+    }
+
+    fn hash(&self, pos: usize) -> Option<usize> {
+        let bytes = self.data.get(pos..pos + MIN_MATCH)?;
+        let key = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        Some((key.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize)
+    }
+
+    fn insert(&mut self, pos: usize) {
+        if let Some(hash) = self.hash(pos) {
+            self.prev[pos] = self.head[hash];
+            self.head[hash] = pos as u32;
+        }
+    }
+
+    /// returns length and distance of the longest match, positions have to be inserted in order
+    fn longest_match(&self, pos: usize) -> (usize, usize) {
+        let Some(hash) = self.hash(pos) else {
+            return (0, 0);
+        };
+        let max_length = MAX_MATCH.min(self.data.len() - pos);
+        let (mut best_length, mut best_dist) = (0, 0);
+        let mut candidate = self.head[hash];
+        for _ in 0..MAX_CHAIN {
+            if candidate == NO_POS || pos - candidate as usize > self.search_window {
+                break;
+            }
+            let candidate_pos = candidate as usize;
+            let length = self.data[candidate_pos..]
+                .iter()
+                .zip(&self.data[pos..pos + max_length])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length > best_length {
+                best_length = length;
+                best_dist = pos - candidate_pos;
+                if length == max_length {
+                    break;
+                }
+            }
+            candidate = self.prev[candidate_pos];
+        }
+        if best_length < MIN_MATCH {
+            (0, 0)
+        } else {
+            (best_length, best_dist)
+        }
+    }
+}
+
+fn tokenize(data: &[u8], search_window: usize) -> Vec<Token> {
+    let mut finder = MatchFinder::new(data, search_window);
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let (length, dist) = finder.longest_match(pos);
+        finder.insert(pos);
+        if length == 0 {
+            tokens.push(Token::Literal(data[pos]));
+            pos += 1;
+            continue;
+        }
+        // lazy matching: a literal followed by a longer match is usually smaller
+        if length < MAX_MATCH && finder.longest_match(pos + 1).0 > length {
+            tokens.push(Token::Literal(data[pos]));
+            pos += 1;
+            continue;
+        }
+        tokens.push(Token::BackReference { dist, length });
+        for i in pos + 1..pos + length {
+            finder.insert(i);
+        }
+        pos += length;
+    }
+    tokens
+}
+
+/// Compresses data with Yaz0, a smaller search window is faster but compresses worse,
+/// it's capped at [`MAX_SEARCH_WINDOW`]
+pub fn compress_yaz0(data: &[u8], search_window: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 2 + 0x10);
+    out.extend_from_slice(YAZ0_MAGIC);
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(&[0; 8]);
+    let mut group_header_pos = 0;
+    for (i, token) in tokenize(data, search_window).into_iter().enumerate() {
+        if i % 8 == 0 {
+            group_header_pos = out.len();
+            out.push(0);
+        }
+        match token {
+            Token::Literal(b) => {
+                out[group_header_pos] |= 0x80 >> (i % 8);
+                out.push(b);
+            }
+            Token::BackReference { dist, length } => {
+                let dist = dist - 1;
+                if length >= 0x12 {
+                    out.push((dist >> 8) as u8);
+                    out.push(dist as u8);
+                    out.push((length - 0x12) as u8);
+                } else {
+                    out.push(((length - 2) << 4 | dist >> 8) as u8);
+                    out.push(dist as u8);
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Compresses data with Yay0, a smaller search window is faster but compresses worse,
+/// it's capped at [`MAX_SEARCH_WINDOW`]
+pub fn compress_yay0(data: &[u8], search_window: usize) -> Vec<u8> {
+    let mut masks = Vec::new();
+    let mut links = Vec::new();
+    let mut chunks = Vec::new();
+    for (i, token) in tokenize(data, search_window).into_iter().enumerate() {
+        if i % 32 == 0 {
+            masks.push(0u32);
+        }
+        match token {
+            Token::Literal(b) => {
+                *masks.last_mut().unwrap() |= 0x8000_0000 >> (i % 32);
+                chunks.push(b);
+            }
+            Token::BackReference { dist, length } => {
+                let dist = dist - 1;
+                if length >= 0x12 {
+                    links.push(dist as u16);
+                    chunks.push((length - 0x12) as u8);
+                } else {
+                    links.push(((length - 2) << 12 | dist) as u16);
+                }
+            }
+        }
+    }
+    let link_off = 0x10 + masks.len() * 4;
+    let chunk_off = link_off + links.len() * 2;
+    let mut out = Vec::with_capacity(chunk_off + chunks.len());
+    out.extend_from_slice(YAY0_MAGIC);
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(&(link_off as u32).to_be_bytes());
+    out.extend_from_slice(&(chunk_off as u32).to_be_bytes());
+    for mask in masks {
+        out.extend_from_slice(&mask.to_be_bytes());
+    }
+    for link in links {
+        out.extend_from_slice(&link.to_be_bytes());
+    }
+    out.extend_from_slice(&chunks);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        compress_yay0, compress_yaz0, decompress_if_compressed, decompress_lz77, decompress_yay0,
+        decompress_yaz0, MAX_SEARCH_WINDOW,
+    };
+
+    fn test_data() -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut state = 0x1234_5678u32;
+        for i in 0..0x20000u32 {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            // mix of random bytes, short and long runs
+            match (i / 0x1000) % 3 {
+                0 => data.push((state >> 24) as u8),
+                1 => data.push((i / 7) as u8),
+                _ => data.push(b"hello world "[(i % 12) as usize]),
+            }
+        }
+        data
+    }
+
+    #[test]
+    pub fn test_yaz0_roundtrip() {
+        let data = test_data();
+        for window in [0x10, MAX_SEARCH_WINDOW] {
+            let compressed = compress_yaz0(&data, window);
+            assert!(compressed.len() < data.len());
+            assert_eq!(decompress_yaz0(&compressed).unwrap(), data);
+        }
+        assert_eq!(
+            decompress_yaz0(&compress_yaz0(&[], 0x1000)).unwrap(),
+            vec![]
+        );
+    }
+
+    #[test]
+    pub fn test_yay0_roundtrip() {
+        let data = test_data();
+        let compressed = compress_yay0(&data, MAX_SEARCH_WINDOW);
+        assert!(compressed.len() < data.len());
+        assert_eq!(decompress_yay0(&compressed).unwrap(), data);
+        assert_eq!(decompress_if_compressed(compressed).unwrap(), data);
+    }
+
+    #[test]
+    pub fn test_yaz0_known() {
+        // "abcabcabcabc" with a back reference
+        let compressed = [
+            b'Y', b'a', b'z', b'0', 0, 0, 0, 12, 0, 0, 0, 0, 0, 0, 0, 0, 0xE0, b'a', b'b', b'c',
+            0x70, 0x02,
+        ];
+        assert_eq!(decompress_yaz0(&compressed).unwrap(), b"abcabcabcabc");
+        assert!(decompress_yaz0(&compressed[..20]).is_err());
+    }
+
+    #[test]
+    pub fn test_lz77_known() {
+        // "abcabcabc" with a back reference, the size is little endian
+        let compressed = [
+            b'L', b'Z', b'7', b'7', 0x10, 9, 0, 0, 0x10, b'a', b'b', b'c', 0x30, 0x02,
+        ];
+        assert_eq!(decompress_lz77(&compressed).unwrap(), b"abcabcabc");
+        assert!(decompress_lz77(&compressed[..12]).is_err());
+    }
+
+    #[test]
+    pub fn test_huge_declared_size() {
+        let mut yaz0 = b"Yaz0".to_vec();
+        yaz0.extend_from_slice(&u32::MAX.to_be_bytes());
+        yaz0.extend_from_slice(&[0; 8]);
+        assert!(decompress_yaz0(&yaz0).is_err());
+        let mut yay0 = b"Yay0".to_vec();
+        yay0.extend_from_slice(&u32::MAX.to_be_bytes());
+        yay0.extend_from_slice(&[0, 0, 0, 0x10, 0, 0, 0, 0x10]);
+        assert!(decompress_yay0(&yay0).is_err());
+        assert!(decompress_lz77(&[b'L', b'Z', b'7', b'7', 0x10, 0xFF, 0xFF, 0xFF]).is_err());
+    }
+}
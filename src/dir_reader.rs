@@ -4,7 +4,7 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use crate::{Fst, FstNode};
+use crate::{Fst, FstError, FstNode};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -15,6 +15,8 @@ pub enum BuildDirError {
     InvalidFilename(OsString),
     #[error("duplicate filename: {0}")]
     DuplicateFilename(String),
+    #[error("fst error: {0}")]
+    Fst(#[from] FstError),
     #[error("required file not found: {0}")]
     NotFound(PathBuf),
     #[error("File {0} is too large, has {1} bytes")]
@@ -50,23 +52,23 @@ fn build_fst_from_directory_tree_rec<P: AsRef<Path> + ?Sized>(
             let _ = dirs.pop();
         } else {
             // files, add them to the fst
-            if !matches!(
-                fst.add_node_iter(
-                    dirs.iter().map(|s| s.as_str()),
-                    FstNode::File {
-                        name: filename,
-                        offset: 0,
-                        length: metadata
-                            .len()
-                            .try_into()
-                            .map_err(|_| BuildDirError::FileTooLarge(path, metadata.len()))?
-                    }
-                ),
-                Ok(None)
+            let length = metadata
+                .len()
+                .try_into()
+                .map_err(|_| BuildDirError::FileTooLarge(path, metadata.len()))?;
+            // names only differing in case are duplicates in the fst
+            match fst.add_node_iter_no_replace(
+                dirs.iter().map(|s| s.as_str()),
+                FstNode::File {
+                    name: filename,
+                    offset: 0,
+                    length,
+                },
             ) {
-                return Err(BuildDirError::DuplicateFilename(
-                    os_filename.to_str().unwrap_or_default().to_string(),
-                ));
+                Err(FstError::AlreadyExists(path)) => {
+                    return Err(BuildDirError::DuplicateFilename(path))
+                }
+                result => result?,
             }
         }
     }
@@ -0,0 +1,331 @@
+use std::io::Cursor;
+
+use binrw::{BinReaderExt, BinWriterExt};
+use thiserror::Error;
+
+use crate::structs::DOLHeader;
+
+pub const DOL_HEADER_SIZE: u32 = 0x100;
+pub const MAX_TEXT_SECTIONS: usize = 7;
+pub const MAX_DATA_SECTIONS: usize = 11;
+// alignment of the section data in the file
+const SECTION_ALIGNMENT: u32 = 0x20;
+
+#[derive(Error, Debug)]
+pub enum DolError {
+    #[error("binrw error: {0}")]
+    BinRW(#[from] binrw::Error),
+    #[error("section data at {offset:#x} with size {size:#x} is out of bounds")]
+    SectionOutOfBounds { offset: u32, size: u32 },
+    #[error("address range {address:#010x} with length {length:#x} is not inside a section")]
+    AddressNotMapped { address: u32, length: usize },
+    #[error("all {0:?} sections are already in use")]
+    TooManySections(DolSectionKind),
+    #[error("new section at {0:#010x} overlaps with an existing section")]
+    SectionOverlap(u32),
+    #[error("section at {address:#010x} with length {length:#x} exceeds the address space")]
+    SectionAddressOverflow { address: u32, length: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DolSectionKind {
+    Text,
+    Data,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DolSection {
+    pub kind: DolSectionKind,
+    /// virtual address the section is loaded to
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+/// end of a section starting at the address, if it fits into the address space
+fn checked_end_address(address: u32, length: usize) -> Result<u32, DolError> {
+    u32::try_from(length)
+        .ok()
+        .and_then(|length| address.checked_add(length))
+        .ok_or(DolError::SectionAddressOverflow { address, length })
+}
+
+impl DolSection {
+    /// sections of a [`Dol`] always end inside the address space
+    pub fn end_address(&self) -> u32 {
+        self.address + self.data.len() as u32
+    }
+
+    fn contains(&self, address: u32, length: usize) -> bool {
+        address >= self.address && (address as u64 + length as u64) <= self.end_address() as u64
+    }
+}
+
+fn align_next(num: u32, alignment: u32) -> u32 {
+    num.wrapping_add(alignment - 1) & !(alignment - 1)
+}
+
+/// Executable of the game, consisting of up to 7 text and 11 data sections that are loaded
+/// to fixed addresses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dol {
+    /// text sections first, then data sections, in header order
+    sections: Vec<DolSection>,
+    pub bss_start: u32,
+    pub bss_size: u32,
+    pub entry_point: u32,
+}
+
+impl Dol {
+    /// creates a DOL without any sections
+    pub fn new(entry_point: u32) -> Self {
+        Self {
+            sections: Vec::new(),
+            bss_start: 0,
+            bss_size: 0,
+            entry_point,
+        }
+    }
+
+    pub fn read(data: &[u8]) -> Result<Self, DolError> {
+        let header: DOLHeader = Cursor::new(data).read_be()?;
+        let text_sections = header
+            .text_off
+            .iter()
+            .zip(header.text_starts.iter())
+            .zip(header.text_sizes.iter())
+            .map(|((off, start), size)| (DolSectionKind::Text, *off, *start, *size));
+        let data_sections = header
+            .data_off
+            .iter()
+            .zip(header.data_starts.iter())
+            .zip(header.data_sizes.iter())
+            .map(|((off, start), size)| (DolSectionKind::Data, *off, *start, *size));
+        let mut sections = Vec::new();
+        for (kind, offset, address, size) in text_sections.chain(data_sections) {
+            if size == 0 {
+                continue;
+            }
+            let section_data = data
+                .get(offset as usize..)
+                .and_then(|d| d.get(..size as usize))
+                .ok_or(DolError::SectionOutOfBounds { offset, size })?;
+            checked_end_address(address, section_data.len())?;
+            sections.push(DolSection {
+                kind,
+                address,
+                data: section_data.to_vec(),
+            });
+        }
+        Ok(Self {
+            sections,
+            bss_start: header.bss_start,
+            bss_size: header.bss_size,
+            entry_point: header.entry_point,
+        })
+    }
+
+    pub fn sections(&self) -> &[DolSection] {
+        &self.sections
+    }
+
+    /// returns the section that contains the entire address range
+    pub fn find_section(&self, address: u32, length: usize) -> Option<&DolSection> {
+        self.sections
+            .iter()
+            .find(|section| section.contains(address, length))
+    }
+
+    fn find_section_mut(&mut self, address: u32, length: usize) -> Option<&mut DolSection> {
+        self.sections
+            .iter_mut()
+            .find(|section| section.contains(address, length))
+    }
+
+    /// file offsets of all sections, in the same order as the sections
+    fn section_offsets(&self) -> Vec<u32> {
+        let mut offset = DOL_HEADER_SIZE;
+        self.sections
+            .iter()
+            .map(|section| {
+                let section_offset = offset;
+                offset = align_next(offset + section.data.len() as u32, SECTION_ALIGNMENT);
+                section_offset
+            })
+            .collect()
+    }
+
+    /// Maps a virtual address to the offset in the serialized DOL
+    pub fn address_to_offset(&self, address: u32) -> Option<u32> {
+        self.sections
+            .iter()
+            .zip(self.section_offsets())
+            .find(|(section, _)| section.contains(address, 1))
+            .map(|(section, offset)| offset + (address - section.address))
+    }
+
+    /// Maps an offset in the serialized DOL to the virtual address
+    pub fn offset_to_address(&self, offset: u32) -> Option<u32> {
+        self.sections
+            .iter()
+            .zip(self.section_offsets())
+            .find(|(section, section_offset)| {
+                offset >= *section_offset && offset - section_offset < section.data.len() as u32
+            })
+            .map(|(section, section_offset)| section.address + (offset - section_offset))
+    }
+
+    pub fn read_bytes(&self, address: u32, length: usize) -> Result<&[u8], DolError> {
+        let section = self
+            .find_section(address, length)
+            .ok_or(DolError::AddressNotMapped { address, length })?;
+        let start = (address - section.address) as usize;
+        Ok(&section.data[start..start + length])
+    }
+
+    pub fn read_u32(&self, address: u32) -> Result<u32, DolError> {
+        let bytes = self.read_bytes(address, 4)?;
+        Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// Overwrites bytes at the virtual address, the range has to be inside a single section
+    pub fn write_bytes(&mut self, address: u32, data: &[u8]) -> Result<(), DolError> {
+        let section =
+            self.find_section_mut(address, data.len())
+                .ok_or(DolError::AddressNotMapped {
+                    address,
+                    length: data.len(),
+                })?;
+        let start = (address - section.address) as usize;
+        section.data[start..start + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    pub fn write_u32(&mut self, address: u32, value: u32) -> Result<(), DolError> {
+        self.write_bytes(address, &value.to_be_bytes())
+    }
+
+    /// Adds a new section, for example a code cave for custom code
+    pub fn add_section(
+        &mut self,
+        kind: DolSectionKind,
+        address: u32,
+        data: Vec<u8>,
+    ) -> Result<(), DolError> {
+        let max_sections = match kind {
+            DolSectionKind::Text => MAX_TEXT_SECTIONS,
+            DolSectionKind::Data => MAX_DATA_SECTIONS,
+        };
+        if self.sections.iter().filter(|s| s.kind == kind).count() >= max_sections {
+            return Err(DolError::TooManySections(kind));
+        }
+        let end = checked_end_address(address, data.len())?;
+        if self
+            .sections
+            .iter()
+            .any(|s| address < s.end_address() && end > s.address)
+        {
+            return Err(DolError::SectionOverlap(address));
+        }
+        let new_section = DolSection {
+            kind,
+            address,
+            data,
+        };
+        // keep text sections in front of data sections
+        match kind {
+            DolSectionKind::Text => {
+                let pos = self
+                    .sections
+                    .iter()
+                    .position(|s| s.kind == DolSectionKind::Data)
+                    .unwrap_or(self.sections.len());
+                self.sections.insert(pos, new_section);
+            }
+            DolSectionKind::Data => self.sections.push(new_section),
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, DolError> {
+        let mut header = DOLHeader {
+            text_off: [0; 7],
+            data_off: [0; 11],
+            text_starts: [0; 7],
+            data_starts: [0; 11],
+            text_sizes: [0; 7],
+            data_sizes: [0; 11],
+            bss_start: self.bss_start,
+            bss_size: self.bss_size,
+            entry_point: self.entry_point,
+        };
+        let offsets = self.section_offsets();
+        let mut out = vec![0; DOL_HEADER_SIZE as usize];
+        let (mut text_idx, mut data_idx) = (0, 0);
+        for (section, offset) in self.sections.iter().zip(offsets) {
+            let (offs, starts, sizes, idx) = match section.kind {
+                DolSectionKind::Text => (
+                    &mut header.text_off[..],
+                    &mut header.text_starts[..],
+                    &mut header.text_sizes[..],
+                    &mut text_idx,
+                ),
+                DolSectionKind::Data => (
+                    &mut header.data_off[..],
+                    &mut header.data_starts[..],
+                    &mut header.data_sizes[..],
+                    &mut data_idx,
+                ),
+            };
+            offs[*idx] = offset;
+            starts[*idx] = section.address;
+            sizes[*idx] = section.data.len() as u32;
+            *idx += 1;
+            out.resize(offset as usize, 0);
+            out.extend_from_slice(&section.data);
+        }
+        out.resize(align_next(out.len() as u32, SECTION_ALIGNMENT) as usize, 0);
+        Cursor::new(&mut out).write_be(&header)?;
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Dol, DolError, DolSectionKind};
+
+    #[test]
+    pub fn test_dol_patch_roundtrip() {
+        let mut dol = Dol {
+            sections: Vec::new(),
+            bss_start: 0x80400000,
+            bss_size: 0x100,
+            entry_point: 0x80004000,
+        };
+        dol.add_section(DolSectionKind::Data, 0x80300000, vec![2; 0x44])
+            .unwrap();
+        dol.add_section(DolSectionKind::Text, 0x80004000, vec![1; 0x100])
+            .unwrap();
+        assert!(dol
+            .add_section(DolSectionKind::Text, 0x800040F0, vec![0; 0x20])
+            .is_err());
+        assert!(matches!(
+            dol.add_section(DolSectionKind::Data, 0xFFFFFFF0, vec![0; 0x20]),
+            Err(DolError::SectionAddressOverflow { .. })
+        ));
+
+        dol.write_u32(0x80004010, 0x4E800020).unwrap();
+        assert!(dol.write_u32(0x800040FE, 0).is_err());
+        assert_eq!(dol.address_to_offset(0x80004010), Some(0x110));
+        assert_eq!(dol.address_to_offset(0x80300004), Some(0x204));
+        assert_eq!(dol.offset_to_address(0x204), Some(0x80300004));
+        assert_eq!(dol.address_to_offset(0x80200000), None);
+
+        let bytes = dol.to_bytes().unwrap();
+        assert_eq!(bytes.len(), 0x260);
+        assert_eq!(&bytes[0x110..0x114], &[0x4E, 0x80, 0x00, 0x20]);
+        let read_dol = Dol::read(&bytes).unwrap();
+        assert_eq!(read_dol, dol);
+        assert_eq!(read_dol.read_u32(0x80004010).unwrap(), 0x4E800020);
+        assert_eq!(read_dol.sections()[0].kind, DolSectionKind::Text);
+    }
+}
@@ -0,0 +1,348 @@
+use std::io::{self, Cursor, Seek, SeekFrom};
+
+use binrw::{binrw, BinReaderExt, BinWriterExt};
+use thiserror::Error;
+
+use crate::dol::{Dol, DolError, DolSectionKind};
+
+const ELF_CLASS_32: u8 = 1;
+const ELF_DATA_BIG_ENDIAN: u8 = 2;
+const ELF_VERSION_CURRENT: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_PPC: u16 = 20;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_STRTAB: u32 = 3;
+const SHT_NOBITS: u32 = 8;
+const SHF_WRITE: u32 = 1;
+const SHF_ALLOC: u32 = 2;
+const SHF_EXECINSTR: u32 = 4;
+
+const ELF_HEADER_SIZE: u16 = 0x34;
+const PROGRAM_HEADER_SIZE: u16 = 0x20;
+const SECTION_HEADER_SIZE: u16 = 0x28;
+
+#[derive(Error, Debug)]
+pub enum ElfError {
+    #[error("io error: {0}")]
+    IO(#[from] io::Error),
+    #[error("binrw error: {0}")]
+    BinRW(#[from] binrw::Error),
+    #[error("dol error: {0}")]
+    Dol(#[from] DolError),
+    #[error("not a 32 bit big endian PowerPC executable")]
+    UnsupportedElf,
+    #[error("segment data at {offset:#x} with size {size:#x} is out of bounds")]
+    SegmentOutOfBounds { offset: u32, size: u32 },
+    #[error("segment at {address:#x} with memory size {size:#x} exceeds the address space")]
+    SegmentAddressOverflow { address: u32, size: u32 },
+}
+
+#[binrw]
+#[brw(big, magic = b"\x7FELF")]
+#[derive(Debug, Clone)]
+pub struct ElfHeader {
+    pub class: u8,
+    pub data: u8,
+    #[brw(pad_after = 9)]
+    pub ident_version: u8,
+    pub elf_type: u16,
+    pub machine: u16,
+    pub version: u32,
+    pub entry: u32,
+    pub ph_off: u32,
+    pub sh_off: u32,
+    pub flags: u32,
+    pub eh_size: u16,
+    pub ph_ent_size: u16,
+    pub ph_num: u16,
+    pub sh_ent_size: u16,
+    pub sh_num: u16,
+    pub sh_str_idx: u16,
+}
+
+#[binrw]
+#[brw(big)]
+#[derive(Debug, Clone)]
+pub struct ElfProgramHeader {
+    pub seg_type: u32,
+    pub offset: u32,
+    pub vaddr: u32,
+    pub paddr: u32,
+    pub file_size: u32,
+    pub mem_size: u32,
+    pub flags: u32,
+    pub align: u32,
+}
+
+#[binrw]
+#[brw(big)]
+#[derive(Debug, Clone)]
+pub struct ElfSectionHeader {
+    pub name: u32,
+    pub sec_type: u32,
+    pub flags: u32,
+    pub addr: u32,
+    pub offset: u32,
+    pub size: u32,
+    pub link: u32,
+    pub info: u32,
+    pub addr_align: u32,
+    pub ent_size: u32,
+}
+
+fn align_next(num: u32, alignment: u32) -> u32 {
+    num.wrapping_add(alignment - 1) & !(alignment - 1)
+}
+
+/// Converts an ELF executable to a DOL, every loadable segment becomes a text section
+/// if it's executable and a data section otherwise. Memory that isn't backed by file
+/// data becomes the BSS
+pub fn elf_to_dol(elf: &[u8]) -> Result<Dol, ElfError> {
+    let mut cursor = Cursor::new(elf);
+    let header: ElfHeader = cursor.read_be()?;
+    if header.class != ELF_CLASS_32
+        || header.data != ELF_DATA_BIG_ENDIAN
+        || header.machine != EM_PPC
+    {
+        return Err(ElfError::UnsupportedElf);
+    }
+    let mut dol = Dol::new(header.entry);
+    let mut bss_range: Option<(u32, u32)> = None;
+    for i in 0..header.ph_num {
+        cursor.seek(SeekFrom::Start(
+            header.ph_off as u64 + i as u64 * header.ph_ent_size as u64,
+        ))?;
+        let program_header: ElfProgramHeader = cursor.read_be()?;
+        if program_header.seg_type != PT_LOAD || program_header.mem_size == 0 {
+            continue;
+        }
+        if program_header.file_size > 0 {
+            let data = elf
+                .get(program_header.offset as usize..)
+                .and_then(|d| d.get(..program_header.file_size as usize))
+                .ok_or(ElfError::SegmentOutOfBounds {
+                    offset: program_header.offset,
+                    size: program_header.file_size,
+                })?;
+            let kind = if program_header.flags & PF_X != 0 {
+                DolSectionKind::Text
+            } else {
+                DolSectionKind::Data
+            };
+            dol.add_section(kind, program_header.vaddr, data.to_vec())?;
+        }
+        if program_header.mem_size > program_header.file_size {
+            // file_size is smaller, so the start can't overflow if the end doesn't
+            let end = program_header
+                .vaddr
+                .checked_add(program_header.mem_size)
+                .ok_or(ElfError::SegmentAddressOverflow {
+                    address: program_header.vaddr,
+                    size: program_header.mem_size,
+                })?;
+            let start = program_header.vaddr + program_header.file_size;
+            bss_range = Some(match bss_range {
+                Some((bss_start, bss_end)) => (bss_start.min(start), bss_end.max(end)),
+                None => (start, end),
+            });
+        }
+    }
+    if let Some((bss_start, bss_end)) = bss_range {
+        dol.bss_start = bss_start;
+        dol.bss_size = bss_end - bss_start;
+    }
+    Ok(dol)
+}
+
+/// Converts a DOL to an ELF executable with one segment and one section per DOL section,
+/// so that it can be loaded into disassemblers
+pub fn dol_to_elf(dol: &Dol) -> Result<Vec<u8>, ElfError> {
+    let has_bss = dol.bss_size > 0;
+    let ph_num = dol.sections().len() + has_bss as usize;
+    let mut program_headers = Vec::with_capacity(ph_num);
+    // first section header is always empty
+    let mut section_headers = vec![ElfSectionHeader {
+        name: 0,
+        sec_type: 0,
+        flags: 0,
+        addr: 0,
+        offset: 0,
+        size: 0,
+        link: 0,
+        info: 0,
+        addr_align: 0,
+        ent_size: 0,
+    }];
+    let mut str_table = vec![0u8];
+    let mut add_name = |name: &str| {
+        let offset = str_table.len() as u32;
+        str_table.extend_from_slice(name.as_bytes());
+        str_table.push(0);
+        offset
+    };
+
+    let mut out = Vec::new();
+    let mut offset = align_next(
+        ELF_HEADER_SIZE as u32 + ph_num as u32 * PROGRAM_HEADER_SIZE as u32,
+        0x20,
+    );
+    let (mut text_idx, mut data_idx) = (0, 0);
+    for section in dol.sections() {
+        let (flags, sh_flags, name) = match section.kind {
+            DolSectionKind::Text => {
+                text_idx += 1;
+                (
+                    PF_R | PF_X,
+                    SHF_ALLOC | SHF_EXECINSTR,
+                    format!(".text{}", text_idx - 1),
+                )
+            }
+            DolSectionKind::Data => {
+                data_idx += 1;
+                (
+                    PF_R | PF_W,
+                    SHF_ALLOC | SHF_WRITE,
+                    format!(".data{}", data_idx - 1),
+                )
+            }
+        };
+        let size = section.data.len() as u32;
+        program_headers.push(ElfProgramHeader {
+            seg_type: PT_LOAD,
+            offset,
+            vaddr: section.address,
+            paddr: section.address,
+            file_size: size,
+            mem_size: size,
+            flags,
+            align: 0x20,
+        });
+        section_headers.push(ElfSectionHeader {
+            name: add_name(&name),
+            sec_type: SHT_PROGBITS,
+            flags: sh_flags,
+            addr: section.address,
+            offset,
+            size,
+            link: 0,
+            info: 0,
+            addr_align: 0x20,
+            ent_size: 0,
+        });
+        out.resize(offset as usize, 0);
+        out.extend_from_slice(&section.data);
+        offset = align_next(offset + size, 0x20);
+    }
+    if has_bss {
+        program_headers.push(ElfProgramHeader {
+            seg_type: PT_LOAD,
+            offset,
+            vaddr: dol.bss_start,
+            paddr: dol.bss_start,
+            file_size: 0,
+            mem_size: dol.bss_size,
+            flags: PF_R | PF_W,
+            align: 0x20,
+        });
+        section_headers.push(ElfSectionHeader {
+            name: add_name(".bss"),
+            sec_type: SHT_NOBITS,
+            flags: SHF_ALLOC | SHF_WRITE,
+            addr: dol.bss_start,
+            offset,
+            size: dol.bss_size,
+            link: 0,
+            info: 0,
+            addr_align: 0x20,
+            ent_size: 0,
+        });
+    }
+    let str_table_name = add_name(".shstrtab");
+    out.resize(offset as usize, 0);
+    section_headers.push(ElfSectionHeader {
+        name: str_table_name,
+        sec_type: SHT_STRTAB,
+        flags: 0,
+        addr: 0,
+        offset,
+        size: str_table.len() as u32,
+        link: 0,
+        info: 0,
+        addr_align: 1,
+        ent_size: 0,
+    });
+    out.extend_from_slice(&str_table);
+    let sh_off = align_next(out.len() as u32, 4);
+    out.resize(sh_off as usize, 0);
+
+    let header = ElfHeader {
+        class: ELF_CLASS_32,
+        data: ELF_DATA_BIG_ENDIAN,
+        ident_version: ELF_VERSION_CURRENT,
+        elf_type: ET_EXEC,
+        machine: EM_PPC,
+        version: ELF_VERSION_CURRENT as u32,
+        entry: dol.entry_point,
+        ph_off: ELF_HEADER_SIZE as u32,
+        sh_off,
+        flags: 0,
+        eh_size: ELF_HEADER_SIZE,
+        ph_ent_size: PROGRAM_HEADER_SIZE,
+        ph_num: ph_num as u16,
+        sh_ent_size: SECTION_HEADER_SIZE,
+        sh_num: section_headers.len() as u16,
+        sh_str_idx: (section_headers.len() - 1) as u16,
+    };
+    let mut cursor = Cursor::new(&mut out);
+    cursor.write_be(&header)?;
+    cursor.write_be(&program_headers)?;
+    cursor.seek(SeekFrom::Start(sh_off as u64))?;
+    cursor.write_be(&section_headers)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dol::{Dol, DolSectionKind};
+
+    use super::{dol_to_elf, elf_to_dol, ElfError};
+
+    #[test]
+    pub fn test_elf_dol_roundtrip() {
+        let mut dol = Dol::new(0x80004000);
+        dol.add_section(DolSectionKind::Text, 0x80004000, vec![1; 0x120])
+            .unwrap();
+        dol.add_section(DolSectionKind::Text, 0x80005000, vec![3; 0x24])
+            .unwrap();
+        dol.add_section(DolSectionKind::Data, 0x80300000, vec![2; 0x40])
+            .unwrap();
+        dol.bss_start = 0x80300040;
+        dol.bss_size = 0x1000;
+        let elf = dol_to_elf(&dol).unwrap();
+        assert_eq!(&elf[..4], b"\x7FELF");
+        assert_eq!(elf_to_dol(&elf).unwrap(), dol);
+    }
+
+    #[test]
+    pub fn test_elf_segment_overflow() {
+        let mut dol = Dol::new(0x80004000);
+        dol.add_section(DolSectionKind::Text, 0x80004000, vec![1; 0x20])
+            .unwrap();
+        dol.bss_start = 0x80004020;
+        dol.bss_size = 0x1000;
+        let mut elf = dol_to_elf(&dol).unwrap();
+        // memory size of the bss segment, which comes after the text segment
+        let ph_off = u32::from_be_bytes(elf[0x1C..0x20].try_into().unwrap()) as usize;
+        elf[ph_off + 0x20 + 0x14..][..4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(
+            elf_to_dol(&elf),
+            Err(ElfError::SegmentAddressOverflow { .. })
+        ));
+    }
+}
@@ -0,0 +1,97 @@
+use std::io::Cursor;
+
+use binrw::{BinRead, BinReaderExt, BinWrite, BinWriterExt};
+use sha1::{Digest, Sha1};
+use thiserror::Error;
+
+use crate::{
+    signature::{verify_ticket, verify_tmd, SignatureError, SignatureStatus},
+    structs::{Ticket, TMD},
+};
+
+// offsets of the padding areas in the serialized structs
+const TICKET_PADDING_OFFSET: usize = 0x1F2;
+const TMD_PADDING_OFFSET: usize = 0x19A;
+
+#[derive(Error, Debug)]
+pub enum FakesignError {
+    #[error("binrw error: {0}")]
+    BinRW(#[from] binrw::Error),
+    #[error("signature error: {0}")]
+    Signature(#[from] SignatureError),
+    #[error("result is not fakesigned, but {0:?}")]
+    VerificationFailed(SignatureStatus),
+}
+
+/// zeroes the signature, then brute forces the padding until the SHA-1
+/// of the signed data starts with 0, which passes the strncmp bug in IOS
+fn fakesign<T>(value: &mut T, sig_len: usize, padding_offset: usize) -> binrw::BinResult<()>
+where
+    T: for<'a> BinRead<Args<'a> = ()> + for<'a> BinWrite<Args<'a> = ()>,
+{
+    let mut buf = Vec::new();
+    Cursor::new(&mut buf).write_be(value)?;
+    buf[4..][..sig_len].fill(0);
+    let signed_start = 4 + sig_len + 0x3C;
+    let mut hasher = Sha1::new();
+    for i in 0..u64::MAX {
+        buf[padding_offset..][..8].copy_from_slice(&i.to_be_bytes());
+        hasher.update(&buf[signed_start..]);
+        if hasher.finalize_reset()[0] == 0 {
+            break;
+        }
+    }
+    *value = Cursor::new(&buf).read_be()?;
+    Ok(())
+}
+
+fn ensure_fakesigned(status: SignatureStatus) -> Result<(), FakesignError> {
+    match status {
+        SignatureStatus::Fakesigned => Ok(()),
+        status => Err(FakesignError::VerificationFailed(status)),
+    }
+}
+
+/// Fakesigns the ticket using its padding area
+pub fn fakesign_ticket(ticket: &mut Ticket) -> Result<(), FakesignError> {
+    fakesign(ticket, ticket.sig.len(), TICKET_PADDING_OFFSET)?;
+    // zeroed signatures don't need the certificate chain
+    ensure_fakesigned(verify_ticket(ticket, &[])?)
+}
+
+/// Fakesigns the TMD using its padding area
+pub fn fakesign_tmd(tmd: &mut TMD) -> Result<(), FakesignError> {
+    fakesign(tmd, tmd.sig.len(), TMD_PADDING_OFFSET)?;
+    ensure_fakesigned(verify_tmd(tmd, &[])?)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use binrw::BinReaderExt;
+
+    use crate::{
+        signature::{verify_ticket, SignatureStatus},
+        structs::Ticket,
+    };
+
+    use super::fakesign_ticket;
+
+    #[test]
+    pub fn test_fakesign_ticket() {
+        let mut buf = vec![0u8; 0x2A4];
+        buf[..4].copy_from_slice(&0x00010001u32.to_be_bytes());
+        buf[0x1DC..][..8].copy_from_slice(b"\0\x01\0\0SOUE");
+        let mut ticket: Ticket = Cursor::new(&buf).read_be().unwrap();
+        ticket.sig.fill(0xAB);
+        ticket.title_key = [7; 16];
+        fakesign_ticket(&mut ticket).unwrap();
+        assert_eq!(
+            verify_ticket(&ticket, &[]).unwrap(),
+            SignatureStatus::Fakesigned
+        );
+        assert_eq!(ticket.title_key, [7; 16]);
+        assert_eq!(&ticket.title_id, b"\0\x01\0\0SOUE");
+    }
+}
@@ -1,13 +1,15 @@
 use std::{
     cmp::Ordering,
-    io::{Read, Seek, SeekFrom, Write},
+    io::{self, Cursor, Read, Seek, SeekFrom, Write},
     iter::once,
 };
 
-use binrw::{binrw, BinReaderExt, BinWriterExt, NullString};
+use binrw::{binrw, BinReaderExt, BinWriterExt};
 use encoding_rs::SHIFT_JIS;
 use thiserror::Error;
 
+const RAW_FST_NODE_SIZE: usize = 12;
+
 #[binrw]
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct RawFstNode {
@@ -23,7 +25,7 @@ pub struct RawFstNode {
     #[bw(ignore)]
     name_offset: u32,
 
-    /// For files, this is the partition offset of the file data. (Wii: >> 2)
+    /// For files, this is the partition offset of the file data. (Wii: >> 2, U8: not shifted)
     ///
     /// For directories, this is the offset of the parent directory in the FST
     offset: u32,
@@ -94,6 +96,14 @@ impl FstNode {
             FstNode::File { name, .. } => name,
         }
     }
+
+    /// not public, changing the name can break the ordering of the siblings
+    fn get_name_mut(&mut self) -> &mut String {
+        match self {
+            FstNode::Directory { name, .. } => name,
+            FstNode::File { name, .. } => name,
+        }
+    }
 }
 
 fn ordering_ignore_case(s1: &str, s2: &str) -> Ordering {
@@ -129,17 +139,238 @@ impl AsMut<FstNode> for FstNode {
     }
 }
 
-fn read_shiftjs<RS: Read + Seek>(rs: &mut RS, offset: u64) -> binrw::BinResult<String> {
-    rs.seek(SeekFrom::Start(offset))?;
-    let null_str = rs.read_be::<NullString>()?;
-    let (res, _, err) = SHIFT_JIS.decode(&null_str);
-    if err {
-        return Err(binrw::Error::Custom {
-            pos: offset,
-            err: Box::new(format!("invalid shiftjis: {}", res)),
-        });
+/// Maximum nesting depth of directories, deeper directories are skipped when reading
+pub const MAX_FST_DEPTH: usize = 256;
+
+/// Problem found while validating an FST
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum FstValidationError {
+    #[error("FST is too short for {0} nodes")]
+    Truncated(u32),
+    #[error("FST root node isn't a directory with parent 0 and at least one node")]
+    InvalidRootNode,
+    #[error("name offset {name_offset:#x} of node {index} is outside of the string table")]
+    NameOutOfRange { index: u32, name_offset: u32 },
+    #[error("name of node {index} isn't valid shift-jis")]
+    InvalidName { index: u32 },
+    #[error("directory {path} ends at node {end}, outside of its parent ending at {parent_end}")]
+    InvalidDirectoryEnd {
+        path: String,
+        end: u32,
+        parent_end: u32,
+    },
+    #[error("directory {path} has parent {parent}, expected {expected}")]
+    InvalidParent {
+        path: String,
+        parent: u32,
+        expected: u32,
+    },
+    #[error("files {first} and {second} overlap")]
+    OverlappingFiles { first: String, second: String },
+    #[error("file {path} at {offset:#x} with length {length:#x} extends past the data size {data_size:#x}")]
+    FileOutOfBounds {
+        path: String,
+        offset: u64,
+        length: u32,
+        data_size: u64,
+    },
+    #[error("duplicate name {0}")]
+    DuplicateName(String),
+    #[error("entries of directory '{0}' are not sorted")]
+    BadOrdering(String),
+    #[error("directory {0} is nested deeper than {MAX_FST_DEPTH} levels")]
+    TooDeep(String),
+}
+
+/// Error when modifying the FST
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum FstError {
+    #[error("{0} doesn't exist")]
+    NotFound(String),
+    #[error("{0} already exists")]
+    AlreadyExists(String),
+    #[error("{0} is a file and can't contain other nodes")]
+    NotADirectory(String),
+    #[error("'{0}' is not a valid name")]
+    InvalidName(String),
+    #[error("can't move {from} into itself at {to}")]
+    MoveIntoItself { from: String, to: String },
+}
+
+fn check_node_name(name: &str) -> Result<(), FstError> {
+    if name.is_empty() || name.contains(['/', '\0']) {
+        return Err(FstError::InvalidName(name.to_string()));
+    }
+    Ok(())
+}
+
+fn split_path(s: &str) -> Vec<&str> {
+    s.split('/').filter(|p| !p.is_empty()).collect()
+}
+
+fn names_equal(parts1: &[&str], parts2: &[&str]) -> bool {
+    parts1.len() == parts2.len()
+        && parts1
+            .iter()
+            .zip(parts2)
+            .all(|(p1, p2)| ordering_ignore_case(p1, p2) == Ordering::Equal)
+}
+
+#[derive(Error, Debug)]
+pub enum FstReadError {
+    #[error("binrw error: {0}")]
+    BinRW(#[from] binrw::Error),
+    #[error("invalid FST ({} problems), first: {}", .0.len(), .0[0])]
+    Invalid(Vec<FstValidationError>),
+}
+
+/// Converts the raw nodes into the tree, fixing every structural problem
+/// and recording it
+struct RawFstParser<'a> {
+    raw_nodes: &'a [RawFstNode],
+    strings: &'a [u8],
+    offset_shift: u32,
+    errors: Vec<FstValidationError>,
+}
+
+impl<'a> RawFstParser<'a> {
+    fn read_name(&mut self, index: u32) -> String {
+        let name_offset = self.raw_nodes[index as usize].name_offset;
+        let name = self
+            .strings
+            .get(name_offset as usize..)
+            .and_then(|rest| rest.split(|b| *b == 0).next().filter(|_| rest.contains(&0)));
+        let Some(name) = name else {
+            self.errors
+                .push(FstValidationError::NameOutOfRange { index, name_offset });
+            return format!("node_{index}");
+        };
+        let (res, _, err) = SHIFT_JIS.decode(name);
+        if err {
+            self.errors.push(FstValidationError::InvalidName { index });
+        }
+        res.into()
+    }
+
+    fn transform_rec(
+        &mut self,
+        parent: u32,
+        children_end: u32,
+        cur_idx: &mut u32,
+        path: &mut Vec<String>,
+    ) -> Vec<FstNode> {
+        let mut nodes = Vec::new();
+        while *cur_idx < children_end {
+            let index = *cur_idx;
+            let node = self.raw_nodes[index as usize].clone();
+            let name = self.read_name(index);
+            *cur_idx += 1;
+            if node.is_directory {
+                path.push(name);
+                if node.offset != parent {
+                    self.errors.push(FstValidationError::InvalidParent {
+                        path: path.join("/"),
+                        parent: node.offset,
+                        expected: parent,
+                    });
+                }
+                let mut end = node.length;
+                if end <= index || end > children_end {
+                    self.errors.push(FstValidationError::InvalidDirectoryEnd {
+                        path: path.join("/"),
+                        end,
+                        parent_end: children_end,
+                    });
+                    end = end.clamp(index + 1, children_end);
+                }
+                if path.len() > MAX_FST_DEPTH {
+                    self.errors
+                        .push(FstValidationError::TooDeep(path.join("/")));
+                    *cur_idx = end;
+                    path.pop();
+                    continue;
+                }
+                let files = self.transform_rec(index, end, cur_idx, path);
+                let name = path.pop().unwrap_or_default();
+                nodes.push(FstNode::Directory { name, files });
+            } else {
+                nodes.push(FstNode::File {
+                    name,
+                    offset: (node.offset as u64) << self.offset_shift,
+                    length: node.length,
+                });
+            }
+        }
+        nodes
+    }
+
+    fn parse(raw_nodes: &'a [RawFstNode], strings: &'a [u8], offset_shift: u32) -> (Fst, Self) {
+        let mut parser = Self {
+            raw_nodes,
+            strings,
+            offset_shift,
+            errors: Vec::new(),
+        };
+        let entries = parser.transform_rec(0, raw_nodes.len() as u32, &mut 1, &mut Vec::new());
+        (Fst { entries }, parser)
     }
-    Ok(res.into())
+}
+
+fn check_root_node(root_node: &RawFstNode) -> Result<(), FstValidationError> {
+    // directory and no name offset
+    if !root_node.is_directory || root_node.offset != 0 || root_node.length == 0 {
+        return Err(FstValidationError::InvalidRootNode);
+    }
+    Ok(())
+}
+
+/// Reads the string table following the nodes, up to the end of the name
+/// with the highest offset
+fn read_string_table<R: Read>(rs: &mut R, raw_nodes: &[RawFstNode]) -> io::Result<Vec<u8>> {
+    let max_name_offset = raw_nodes
+        .iter()
+        .map(|node| node.name_offset as usize)
+        .max()
+        .unwrap_or(0);
+    let mut buf = Vec::new();
+    loop {
+        let start = buf.len();
+        buf.resize(start + 0x200, 0);
+        let read = rs.read(&mut buf[start..])?;
+        buf.truncate(start + read);
+        if read == 0
+            || buf
+                .get(max_name_offset..)
+                .is_some_and(|rest| rest.contains(&0))
+        {
+            return Ok(buf);
+        }
+    }
+}
+
+fn parse_fst_bytes(
+    data: &[u8],
+    offset_shift: u32,
+) -> Result<(Fst, Vec<FstValidationError>), FstReadError> {
+    let mut cursor = Cursor::new(data);
+    let root_node: RawFstNode = cursor.read_be()?;
+    check_root_node(&root_node).map_err(|e| FstReadError::Invalid(vec![e]))?;
+    let mut errors = Vec::new();
+    let mut node_count = root_node.length;
+    let max_node_count = (data.len() / RAW_FST_NODE_SIZE) as u32;
+    if node_count > max_node_count {
+        errors.push(FstValidationError::Truncated(node_count));
+        node_count = max_node_count;
+    }
+    let mut raw_nodes = Vec::with_capacity(node_count as usize);
+    raw_nodes.push(root_node);
+    for _ in 1..node_count {
+        raw_nodes.push(cursor.read_be()?);
+    }
+    let strings = &data[node_count as usize * RAW_FST_NODE_SIZE..];
+    let (fst, parser) = RawFstParser::parse(&raw_nodes, strings, offset_shift);
+    errors.extend(parser.errors);
+    Ok((fst, errors))
 }
 
 /// Implements the file system table
@@ -187,103 +418,68 @@ where
     Some(cur_node)
 }
 
-pub fn remove_node_iter<'a, 'b, I>(nodes: &'a mut Vec<FstNode>, iter: I) -> Option<FstNode>
+pub fn remove_node_iter<'a, 'b, I>(
+    nodes: &'a mut Vec<FstNode>,
+    iter: I,
+) -> Result<FstNode, FstError>
 where
     I: Iterator<Item = &'b str>,
 {
-    let mut peek_iter = iter.peekable();
-    let cur_part = peek_iter.next()?;
-    if peek_iter.peek().is_none() {
-        // iterator is exhausted
-        let idx = nodes.iter().position(|n| n.get_name() == cur_part)?;
-        return Some(nodes.remove(idx));
-    }
-    let mut cur_node = nodes.iter_mut().find(|n| n.get_name() == cur_part)?;
-    while let Some(cur_part) = peek_iter.next() {
-        match cur_node {
-            FstNode::Directory { files, .. } => {
-                if peek_iter.peek().is_some() {
-                    cur_node = files.iter_mut().find(|n| n.get_name() == cur_part)?;
-                } else {
-                    // iterator is exhausted
-                    let idx = files.iter().position(|n| n.get_name() == cur_part)?;
-                    return Some(files.remove(idx));
-                }
-            }
+    let parts: Vec<&str> = iter.collect();
+    let not_found = || FstError::NotFound(parts.join("/"));
+    let (name, parents) = parts.split_last().ok_or_else(not_found)?;
+    let mut dir_node_files = nodes;
+    for (i, cur_part) in parents.iter().enumerate() {
+        dir_node_files = match dir_node_files.iter_mut().find(|n| n.get_name() == cur_part) {
+            Some(FstNode::Directory { files, .. }) => files,
             // we can't descend into files
-            FstNode::File { .. } => return None,
-        }
+            Some(FstNode::File { .. }) => {
+                return Err(FstError::NotADirectory(parts[..=i].join("/")))
+            }
+            None => return Err(not_found()),
+        };
     }
-    None
+    let idx = dir_node_files
+        .iter()
+        .position(|n| n.get_name() == name)
+        .ok_or_else(not_found)?;
+    Ok(dir_node_files.remove(idx))
 }
 
+/// Adds the node to the directory at the path, missing directories are created.
+/// An existing node with the same name (ignoring case) is replaced and returned
 pub fn add_node_iter<'a, 'b, I>(
     nodes: &'a mut Vec<FstNode>,
-    mut iter: I,
+    iter: I,
     mut new_node: FstNode,
-) -> Result<Option<FstNode>, ()>
+) -> Result<Option<FstNode>, FstError>
 where
     I: Iterator<Item = &'b str>,
 {
-    let mut dir_node_files = if let Some(cur_part) = iter.next() {
+    let parts: Vec<&str> = iter.collect();
+    // check everything first to not leave behind new directories
+    for part in parts.iter().chain(once(&new_node.get_name().as_str())) {
+        check_node_name(part)?;
+    }
+    let mut dir_node_files = nodes;
+    for (i, cur_part) in parts.iter().enumerate() {
         // we search the name we want to insert and assume the lists are ordered
         // we either get back the actual position of the element or
         // the position to insert the new node to remain sorted
-        let node = match nodes
+        let pos = match dir_node_files
             .binary_search_by(|probe| ordering_ignore_case(probe.get_name(), cur_part))
         {
-            Ok(pos) => &mut nodes[pos],
+            Ok(pos) => pos,
             Err(pos) => {
-                // we need to either create a new directory
-                let new_dir_node = FstNode::Directory {
-                    name: cur_part.to_string(),
-                    files: Vec::new(),
-                };
-                nodes.insert(pos, new_dir_node);
-                &mut nodes[pos]
+                // we need to create a new directory
+                dir_node_files.insert(pos, FstNode::create_dir(cur_part.to_string()));
+                pos
             }
         };
-        match node {
+        dir_node_files = match &mut dir_node_files[pos] {
             FstNode::Directory { files, .. } => files,
             // can't insert when the node is a file
-            FstNode::File { .. } => return Err(()),
-        }
-    } else {
-        // we have to insert at the root node
-        match nodes
-            .binary_search_by(|probe| ordering_ignore_case(probe.get_name(), new_node.get_name()))
-        {
-            Ok(pos) => {
-                // since the path is exhausted, replace the node completely
-                std::mem::swap(&mut new_node, &mut nodes[pos]);
-                return Ok(Some(new_node));
-            }
-            Err(pos) => {
-                // new file, vec is still sorted
-                nodes.insert(pos, new_node);
-                return Ok(None);
-            }
-        }
-    };
-    for cur_part in iter {
-        let node = match dir_node_files
-            .binary_search_by(|probe| ordering_ignore_case(probe.get_name(), cur_part))
-        {
-            Ok(pos) => &mut dir_node_files[pos],
-            Err(pos) => {
-                // we need to either create a new directory
-                let new_dir_node = FstNode::Directory {
-                    name: cur_part.to_string(),
-                    files: Vec::new(),
-                };
-                dir_node_files.insert(pos, new_dir_node);
-                &mut dir_node_files[pos]
-            }
-        };
-        dir_node_files = match node {
-            FstNode::Directory { files, .. } => files,
-            // can't insert when the node is a file
-            FstNode::File { .. } => return Err(()),
+            FstNode::File { .. } => return Err(FstError::NotADirectory(parts[..=i].join("/"))),
         };
     }
     match dir_node_files
@@ -302,60 +498,397 @@ where
     }
 }
 
+/// Glob pattern for paths in the FST, segments are separated by `/`.
+/// `*` matches any amount of characters and `?` a single character inside a segment,
+/// a `**` segment matches any amount of directories
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FstGlob {
+    segments: Vec<Vec<char>>,
+    ignore_case: bool,
+}
+
+fn chars_equal(c1: char, c2: char, ignore_case: bool) -> bool {
+    if ignore_case {
+        c1.eq_ignore_ascii_case(&c2)
+    } else {
+        c1 == c2
+    }
+}
+
+fn glob_match_segment(pattern: &[char], name: &[char], ignore_case: bool) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => {
+            (0..=name.len()).any(|i| glob_match_segment(rest, &name[i..], ignore_case))
+        }
+        Some((c, rest)) => match name.split_first() {
+            Some((n, name_rest)) if *c == '?' || chars_equal(*c, *n, ignore_case) => {
+                glob_match_segment(rest, name_rest, ignore_case)
+            }
+            _ => false,
+        },
+    }
+}
+
+impl FstGlob {
+    pub fn new(pattern: &str, ignore_case: bool) -> Self {
+        let segments = pattern
+            .split('/')
+            .filter(|p| !p.is_empty())
+            .map(|p| p.chars().collect())
+            .collect();
+        Self {
+            segments,
+            ignore_case,
+        }
+    }
+
+    pub fn matches<S: AsRef<str>>(&self, path: &[S]) -> bool {
+        let path: Vec<Vec<char>> = path.iter().map(|p| p.as_ref().chars().collect()).collect();
+        self.matches_rec(&self.segments, &path)
+    }
+
+    /// Matches a slash separated path
+    pub fn matches_path(&self, path: &str) -> bool {
+        let parts: Vec<&str> = path.split('/').filter(|p| !p.is_empty()).collect();
+        self.matches(&parts)
+    }
+
+    fn matches_rec(&self, segments: &[Vec<char>], path: &[Vec<char>]) -> bool {
+        match segments.split_first() {
+            None => path.is_empty(),
+            Some((segment, rest)) if segment.as_slice() == ['*', '*'] => {
+                (0..=path.len()).any(|i| self.matches_rec(rest, &path[i..]))
+            }
+            Some((segment, rest)) => match path.split_first() {
+                Some((name, path_rest)) => {
+                    glob_match_segment(segment, name, self.ignore_case)
+                        && self.matches_rec(rest, path_rest)
+                }
+                None => false,
+            },
+        }
+    }
+}
+
+/// Node of the FST with its full path and nesting depth, nodes in the root have depth 0
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FstEntry<'a> {
+    pub path: String,
+    pub depth: usize,
+    pub node: &'a FstNode,
+}
+
+/// Depth-first iterator over all nodes of the FST, directories come before their contents
+pub struct FstWalk<'a> {
+    stack: Vec<std::slice::Iter<'a, FstNode>>,
+    path: Vec<&'a str>,
+}
+
+impl<'a> FstWalk<'a> {
+    fn new(entries: &'a [FstNode]) -> Self {
+        Self {
+            stack: vec![entries.iter()],
+            path: Vec::new(),
+        }
+    }
+}
+
+impl<'a> Iterator for FstWalk<'a> {
+    type Item = FstEntry<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.stack.last_mut()?.next() {
+                Some(node) => {
+                    let depth = self.path.len();
+                    let mut path = self.path.join("/");
+                    if !path.is_empty() {
+                        path.push('/');
+                    }
+                    path.push_str(node.get_name());
+                    if let FstNode::Directory { files, .. } = node {
+                        self.stack.push(files.iter());
+                        self.path.push(node.get_name());
+                    }
+                    return Some(FstEntry { path, depth, node });
+                }
+                None => {
+                    self.stack.pop();
+                    self.path.pop();
+                }
+            }
+        }
+    }
+}
+
+/// Node that can be modified while walking the FST
+pub struct FstEntryMut<'a> {
+    pub path: String,
+    pub depth: usize,
+    pub node: &'a mut FstNode,
+}
+
+fn node_at<'a>(entries: &'a mut [FstNode], indices: &[usize]) -> Option<&'a mut FstNode> {
+    let (first, rest) = indices.split_first()?;
+    let mut node = entries.get_mut(*first)?;
+    for idx in rest {
+        node = match node {
+            FstNode::Directory { files, .. } => files.get_mut(*idx)?,
+            FstNode::File { .. } => return None,
+        };
+    }
+    Some(node)
+}
+
+/// Depth-first walker that allows modifying every node.
+/// A directory is returned before its children, so changes to its `files` take
+/// effect for the rest of the walk. [`FstWalkerMut::skip_children`] prevents
+/// descending into it. Use it like an iterator with `while let Some(entry) = walker.next()`
+pub struct FstWalkerMut<'a> {
+    entries: &'a mut Vec<FstNode>,
+    /// indices of the node returned last
+    current: Vec<usize>,
+    started: bool,
+    skip_children: bool,
+}
+
+impl<'a> FstWalkerMut<'a> {
+    /// Don't visit the children of the directory returned last
+    pub fn skip_children(&mut self) {
+        self.skip_children = true;
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<FstEntryMut<'_>> {
+        if !self.started {
+            self.started = true;
+            self.current.push(0);
+        } else {
+            let descend = !self.skip_children
+                && matches!(
+                    node_at(self.entries, &self.current),
+                    Some(FstNode::Directory { files, .. }) if !files.is_empty()
+                );
+            self.skip_children = false;
+            if descend {
+                self.current.push(0);
+            } else {
+                *self.current.last_mut()? += 1;
+            }
+        }
+        // go up until there is a next sibling
+        while node_at(self.entries, &self.current).is_none() {
+            self.current.pop();
+            *self.current.last_mut()? += 1;
+        }
+        let mut names = Vec::with_capacity(self.current.len());
+        for i in 1..=self.current.len() {
+            names.push(
+                node_at(self.entries, &self.current[..i])?
+                    .get_name()
+                    .clone(),
+            );
+        }
+        Some(FstEntryMut {
+            path: names.join("/"),
+            depth: self.current.len() - 1,
+            node: node_at(self.entries, &self.current)?,
+        })
+    }
+}
+
+fn find_node_ignore_case<'a>(nodes: &'a [FstNode], path: &str) -> Option<&'a FstNode> {
+    let mut nodes = nodes;
+    let mut found = None;
+    for part in path.split('/').filter(|p| !p.is_empty()) {
+        let node = nodes
+            .iter()
+            .find(|n| n.get_name().eq_ignore_ascii_case(part))?;
+        nodes = match node {
+            FstNode::Directory { files, .. } => files,
+            FstNode::File { .. } => &[],
+        };
+        found = Some(node);
+    }
+    found
+}
+
 impl Fst {
     pub fn new() -> Self {
         Default::default()
     }
 
     pub fn read<RS: Read + Seek>(rs: &mut RS, offset: u64) -> binrw::BinResult<Self> {
+        Self::read_with_shift(rs, offset, 2)
+    }
+
+    /// reads the FST, file offsets are shifted left by `offset_shift`
+    pub(crate) fn read_with_shift<RS: Read + Seek>(
+        rs: &mut RS,
+        offset: u64,
+        offset_shift: u32,
+    ) -> binrw::BinResult<Self> {
+        let to_binrw_err = |err: FstValidationError| binrw::Error::Custom {
+            pos: offset,
+            err: Box::new(err.to_string()),
+        };
         rs.seek(SeekFrom::Start(offset))?;
         let root_node: RawFstNode = rs.read_be()?;
-        // directory and no name offset
-        if !root_node.is_directory || root_node.offset != 0 || root_node.length == 0 {
-            return Err(binrw::Error::Custom {
-                pos: offset,
-                err: Box::new(format!("invalid FST first node: {:?}", &root_node)),
-            });
-        }
+        check_root_node(&root_node).map_err(to_binrw_err)?;
         let total_node_count = root_node.length - 1;
-        let mut nodes = Vec::with_capacity(total_node_count as usize);
+        // the count isn't validated yet, so don't trust it for allocating
+        let mut nodes = Vec::with_capacity(total_node_count.min(0x10000) as usize);
         nodes.push(root_node);
         for _ in 0..total_node_count {
             nodes.push(rs.read_be::<RawFstNode>()?);
         }
-        let str_offset = rs.stream_position()?;
+        let strings = read_string_table(rs, &nodes)?;
+        let (fst, parser) = RawFstParser::parse(&nodes, &strings, offset_shift);
+        // the parent offset is recalculated when writing, so it doesn't matter here
+        match parser
+            .errors
+            .into_iter()
+            .find(|e| !matches!(e, FstValidationError::InvalidParent { .. }))
+        {
+            Some(err) => Err(to_binrw_err(err)),
+            None => Ok(fst),
+        }
+    }
 
-        let fst_nodes =
-            Self::transform_fst_rec(rs, str_offset, &nodes, total_node_count + 1, &mut 1)?;
-        Ok(Self { entries: fst_nodes })
+    /// Parses a Wii FST (like `sys/fst.bin`) and validates it, every problem
+    /// is returned as an error.
+    /// If `data_size` is given, files have to be inside of it
+    pub fn read_validated(data: &[u8], data_size: Option<u64>) -> Result<Self, FstReadError> {
+        let (fst, mut errors) = parse_fst_bytes(data, 2)?;
+        errors.extend(fst.validate(data_size));
+        if errors.is_empty() {
+            Ok(fst)
+        } else {
+            Err(FstReadError::Invalid(errors))
+        }
     }
 
-    fn transform_fst_rec<RS: Read + Seek>(
-        rs: &mut RS,
-        str_offset: u64,
-        raw_nodes: &Vec<RawFstNode>,
-        children_end: u32,
-        cur_idx: &mut u32,
-    ) -> binrw::BinResult<Vec<FstNode>> {
-        let mut nodes = Vec::with_capacity(raw_nodes.len());
-        while *cur_idx < children_end {
-            let node = &raw_nodes[*cur_idx as usize];
-            let name = read_shiftjs(rs, str_offset + node.name_offset as u64)?;
-            *cur_idx += 1;
-            if node.is_directory {
-                let files =
-                    Self::transform_fst_rec(rs, str_offset, raw_nodes, node.length, cur_idx)?;
-                nodes.push(FstNode::Directory { name, files });
-            } else {
-                nodes.push(FstNode::File {
-                    name,
-                    offset: (node.offset as u64) << 2,
-                    length: node.length,
-                });
+    /// Parses a Wii FST (like `sys/fst.bin`) and fixes all problems that can be fixed,
+    /// returning them alongside the FST. Overlapping files and invalid parent offsets
+    /// are only reported. Only an invalid root node can't be repaired
+    pub fn read_repaired(
+        data: &[u8],
+        data_size: Option<u64>,
+    ) -> Result<(Self, Vec<FstValidationError>), FstReadError> {
+        let (mut fst, mut errors) = parse_fst_bytes(data, 2)?;
+        errors.extend(fst.repair(data_size));
+        Ok((fst, errors))
+    }
+
+    /// Checks the ordering and uniqueness of names, that files don't overlap
+    /// and, if `data_size` is given, that files end before it.
+    /// Files with identical offset and length are allowed, they share the same data
+    pub fn validate(&self, data_size: Option<u64>) -> Vec<FstValidationError> {
+        let mut errors = Vec::new();
+        Self::validate_dir_rec(&self.entries, &mut Vec::new(), &mut errors);
+        let mut extents = Vec::new();
+        for entry in self.files() {
+            if let FstNode::File { offset, length, .. } = entry.node {
+                if let Some(data_size) = data_size {
+                    if offset + *length as u64 > data_size {
+                        errors.push(FstValidationError::FileOutOfBounds {
+                            path: entry.path.clone(),
+                            offset: *offset,
+                            length: *length,
+                            data_size,
+                        });
+                    }
+                }
+                if *length > 0 {
+                    extents.push((*offset, *length as u64, entry.path));
+                }
+            }
+        }
+        extents.sort();
+        // extent reaching the furthest so far
+        let mut furthest: Option<(u64, u64, &str)> = None;
+        for (offset, length, path) in &extents {
+            if let Some((prev_offset, prev_length, prev_path)) = furthest {
+                if (prev_offset, prev_length) == (*offset, *length) {
+                    continue;
+                }
+                if *offset < prev_offset + prev_length {
+                    errors.push(FstValidationError::OverlappingFiles {
+                        first: prev_path.to_string(),
+                        second: path.clone(),
+                    });
+                }
+                if offset + length <= prev_offset + prev_length {
+                    continue;
+                }
             }
+            furthest = Some((*offset, *length, path));
         }
-        assert_eq!(*cur_idx, children_end);
-        Ok(nodes)
+        errors
+    }
+
+    fn validate_dir_rec(
+        nodes: &[FstNode],
+        path: &mut Vec<String>,
+        errors: &mut Vec<FstValidationError>,
+    ) {
+        if nodes
+            .windows(2)
+            .any(|pair| pair[0].node_compare(&pair[1]) == Ordering::Greater)
+        {
+            errors.push(FstValidationError::BadOrdering(path.join("/")));
+        }
+        let mut sorted: Vec<&FstNode> = nodes.iter().collect();
+        sorted.sort_by(|n1, n2| n1.node_compare(n2));
+        for pair in sorted.windows(2) {
+            if pair[0].node_compare(pair[1]) == Ordering::Equal {
+                path.push(pair[1].get_name().clone());
+                errors.push(FstValidationError::DuplicateName(path.join("/")));
+                path.pop();
+            }
+        }
+        for node in nodes {
+            if let FstNode::Directory { name, files } = node {
+                path.push(name.clone());
+                if path.len() > MAX_FST_DEPTH {
+                    errors.push(FstValidationError::TooDeep(path.join("/")));
+                } else {
+                    Self::validate_dir_rec(files, path, errors);
+                }
+                path.pop();
+            }
+        }
+    }
+
+    /// Fixes the ordering, removes later duplicates and files starting after
+    /// `data_size` and shortens files extending past it. Directories nested
+    /// deeper than [`MAX_FST_DEPTH`] are removed.
+    /// Returns the problems found before repairing
+    pub fn repair(&mut self, data_size: Option<u64>) -> Vec<FstValidationError> {
+        let errors = self.validate(data_size);
+        Self::repair_rec(&mut self.entries, data_size, 1);
+        errors
+    }
+
+    fn repair_rec(nodes: &mut Vec<FstNode>, data_size: Option<u64>, depth: usize) {
+        nodes.sort_by(FstNode::node_compare);
+        nodes.dedup_by(|n1, n2| n1.node_compare(n2) == Ordering::Equal);
+        nodes.retain_mut(|node| match node {
+            FstNode::Directory { .. } if depth > MAX_FST_DEPTH => false,
+            FstNode::Directory { files, .. } => {
+                Self::repair_rec(files, data_size, depth + 1);
+                true
+            }
+            FstNode::File { offset, length, .. } => match data_size {
+                Some(data_size) if *offset >= data_size => false,
+                Some(data_size) => {
+                    *length = (*length as u64).min(data_size - *offset) as u32;
+                    true
+                }
+                None => true,
+            },
+        });
     }
 
     pub fn get_entries_mut(&mut self) -> &mut Vec<FstNode> {
@@ -390,18 +923,20 @@ impl Fst {
         find_node_iter_mut(&mut self.entries, iter)
     }
 
-    pub fn remove_node_path(&mut self, s: &str) -> Option<FstNode> {
+    pub fn remove_node_path(&mut self, s: &str) -> Result<FstNode, FstError> {
         self.remove_node_iter(s.split('/').filter(|p| !p.is_empty()))
     }
 
-    pub fn remove_node_iter<'a, 'b, I>(&mut self, iter: I) -> Option<FstNode>
+    pub fn remove_node_iter<'a, 'b, I>(&mut self, iter: I) -> Result<FstNode, FstError>
     where
         I: Iterator<Item = &'b str>,
     {
         remove_node_iter(&mut self.entries, iter)
     }
 
-    pub fn add_node_path(&mut self, s: &str, node: FstNode) -> Result<Option<FstNode>, ()> {
+    /// Adds the node to the directory at the path, missing directories are created.
+    /// An existing node with the same name (ignoring case) is replaced and returned
+    pub fn add_node_path(&mut self, s: &str, node: FstNode) -> Result<Option<FstNode>, FstError> {
         self.add_node_iter(s.split('/').filter(|p| !p.is_empty()), node)
     }
 
@@ -409,13 +944,227 @@ impl Fst {
         &mut self,
         iter: I,
         node: FstNode,
-    ) -> Result<Option<FstNode>, ()>
+    ) -> Result<Option<FstNode>, FstError>
     where
         I: Iterator<Item = &'b str>,
     {
         add_node_iter(&mut self.entries, iter, node)
     }
 
+    /// Like [`Fst::add_node_path`], but fails with [`FstError::AlreadyExists`]
+    /// instead of replacing an existing node
+    pub fn add_node_path_no_replace(&mut self, s: &str, node: FstNode) -> Result<(), FstError> {
+        self.add_node_iter_no_replace(s.split('/').filter(|p| !p.is_empty()), node)
+    }
+
+    pub fn add_node_iter_no_replace<'a, 'b, I>(
+        &mut self,
+        iter: I,
+        node: FstNode,
+    ) -> Result<(), FstError>
+    where
+        I: Iterator<Item = &'b str>,
+    {
+        let mut parts: Vec<&str> = iter.collect();
+        let name = node.get_name().clone();
+        parts.push(&name);
+        self.check_destination(&parts)?;
+        parts.pop();
+        self.add_node_iter(parts.into_iter(), node)?;
+        Ok(())
+    }
+
+    /// Checks that a node can be added at the path: every existing parent is a
+    /// directory and there is no node with the same name (ignoring case)
+    fn check_destination(&self, parts: &[&str]) -> Result<(), FstError> {
+        let (name, parents) = parts
+            .split_last()
+            .ok_or_else(|| FstError::InvalidName(String::new()))?;
+        // missing parents get created, so their names have to be valid as well
+        for part in parts {
+            check_node_name(part)?;
+        }
+        let mut nodes = self.entries.as_slice();
+        for (i, part) in parents.iter().enumerate() {
+            match nodes
+                .iter()
+                .find(|n| ordering_ignore_case(n.get_name(), part) == Ordering::Equal)
+            {
+                Some(FstNode::Directory { files, .. }) => nodes = files,
+                Some(FstNode::File { .. }) => {
+                    return Err(FstError::NotADirectory(parts[..=i].join("/")))
+                }
+                // missing directories get created
+                None => return Ok(()),
+            }
+        }
+        if nodes
+            .iter()
+            .any(|n| ordering_ignore_case(n.get_name(), name) == Ordering::Equal)
+        {
+            return Err(FstError::AlreadyExists(parts.join("/")));
+        }
+        Ok(())
+    }
+
+    /// Renames the node at the path, keeping its siblings sorted.
+    /// Fails if a sibling already has the new name (ignoring case)
+    pub fn rename_node_path(&mut self, s: &str, new_name: &str) -> Result<(), FstError> {
+        check_node_name(new_name)?;
+        let parts = split_path(s);
+        let not_found = || FstError::NotFound(s.to_string());
+        let (name, parents) = parts.split_last().ok_or_else(not_found)?;
+        let siblings = if parents.is_empty() {
+            &mut self.entries
+        } else {
+            match self.find_node_iter_mut(parents.iter().copied()) {
+                Some(FstNode::Directory { files, .. }) => files,
+                _ => return Err(not_found()),
+            }
+        };
+        let idx = siblings
+            .iter()
+            .position(|n| n.get_name() == name)
+            .ok_or_else(not_found)?;
+        // only changing the case doesn't conflict with the node itself
+        if ordering_ignore_case(name, new_name) != Ordering::Equal
+            && siblings
+                .iter()
+                .any(|n| ordering_ignore_case(n.get_name(), new_name) == Ordering::Equal)
+        {
+            let mut new_path = parents.to_vec();
+            new_path.push(new_name);
+            return Err(FstError::AlreadyExists(new_path.join("/")));
+        }
+        let mut node = siblings.remove(idx);
+        *node.get_name_mut() = new_name.to_string();
+        let pos = siblings
+            .binary_search_by(|probe| probe.node_compare(&node))
+            .unwrap_or_else(|pos| pos);
+        siblings.insert(pos, node);
+        Ok(())
+    }
+
+    /// Moves the node (with all its children) to the new path, which includes the
+    /// name of the node. Missing parent directories are created
+    pub fn move_node_path(&mut self, from: &str, to: &str) -> Result<(), FstError> {
+        let from_parts = split_path(from);
+        let to_parts = split_path(to);
+        if self.find_node_iter(from_parts.iter().copied()).is_none() {
+            return Err(FstError::NotFound(from.to_string()));
+        }
+        if names_equal(&from_parts, &to_parts) {
+            // same node, at most the case of the name changes
+            return self.rename_node_path(from, to_parts[to_parts.len() - 1]);
+        }
+        if to_parts.len() > from_parts.len()
+            && names_equal(&from_parts, &to_parts[..from_parts.len()])
+        {
+            return Err(FstError::MoveIntoItself {
+                from: from.to_string(),
+                to: to.to_string(),
+            });
+        }
+        self.check_destination(&to_parts)?;
+        let node = self.remove_node_iter(from_parts.iter().copied())?;
+        self.insert_checked(&to_parts, node)
+    }
+
+    /// Copies the node (with all its children) to the new path, which includes the
+    /// name of the copy. Missing parent directories are created
+    pub fn copy_node_path(&mut self, from: &str, to: &str) -> Result<(), FstError> {
+        let to_parts = split_path(to);
+        let node = self
+            .find_node_path(from)
+            .ok_or_else(|| FstError::NotFound(from.to_string()))?
+            .clone();
+        self.check_destination(&to_parts)?;
+        self.insert_checked(&to_parts, node)
+    }
+
+    /// Inserts a node at a destination that passed [`Fst::check_destination`]
+    fn insert_checked(&mut self, parts: &[&str], mut node: FstNode) -> Result<(), FstError> {
+        let (name, parents) = parts.split_last().unwrap();
+        *node.get_name_mut() = name.to_string();
+        // all names are valid and all existing parents are directories, an error here
+        // means the check missed something
+        self.add_node_iter(parents.iter().copied(), node)?;
+        Ok(())
+    }
+
+    /// Finds a node by path, ignoring the ASCII case of all names
+    pub fn find_node_path_ignore_case<'a>(&'a self, s: &str) -> Option<&'a FstNode> {
+        find_node_ignore_case(&self.entries, s)
+    }
+
+    /// Returns the actual path of a node that was searched ignoring the case
+    pub fn resolve_path_ignore_case(&self, s: &str) -> Option<String> {
+        let mut nodes = self.entries.as_slice();
+        let mut resolved = Vec::new();
+        for part in s.split('/').filter(|p| !p.is_empty()) {
+            let node = nodes
+                .iter()
+                .find(|n| n.get_name().eq_ignore_ascii_case(part))?;
+            resolved.push(node.get_name().as_str());
+            nodes = match node {
+                FstNode::Directory { files, .. } => files,
+                FstNode::File { .. } => &[],
+            };
+        }
+        (!resolved.is_empty()).then(|| resolved.join("/"))
+    }
+
+    /// Iterates depth-first over all nodes with their full path and depth
+    pub fn walk(&self) -> FstWalk<'_> {
+        FstWalk::new(&self.entries)
+    }
+
+    /// Iterates over all files with their full path and depth
+    pub fn files(&self) -> impl Iterator<Item = FstEntry<'_>> {
+        self.walk().filter(|entry| entry.node.is_file())
+    }
+
+    /// Iterates over all directories with their full path and depth
+    pub fn dirs(&self) -> impl Iterator<Item = FstEntry<'_>> {
+        self.walk().filter(|entry| entry.node.is_dir())
+    }
+
+    /// Walks depth-first over all nodes, allowing to modify them
+    pub fn walk_mut(&mut self) -> FstWalkerMut<'_> {
+        FstWalkerMut {
+            entries: &mut self.entries,
+            current: Vec::new(),
+            started: false,
+            skip_children: false,
+        }
+    }
+
+    /// Iterates over all nodes matching the glob
+    pub fn glob<'a>(&'a self, glob: &'a FstGlob) -> impl Iterator<Item = FstEntry<'a>> + 'a {
+        self.walk().filter(|entry| glob.matches_path(&entry.path))
+    }
+
+    /// Removes all nodes matching the glob, including everything in matching
+    /// directories, and returns the paths of the removed nodes
+    pub fn remove_glob(&mut self, glob: &FstGlob) -> Vec<String> {
+        let mut removed: Vec<String> = Vec::new();
+        for entry in self.glob(glob) {
+            // directories come before their contents, so only the last removed node
+            // can contain this one
+            let in_removed_dir = removed.last().is_some_and(|dir| {
+                entry
+                    .path
+                    .strip_prefix(dir.as_str())
+                    .is_some_and(|rest| rest.starts_with('/'))
+            });
+            if !in_removed_dir {
+                removed.push(entry.path);
+            }
+        }
+        removed.retain(|path| self.remove_node_path(path).is_ok());
+        removed
+    }
+
     /// Prints the entire node tree, using indents to mark folders and their files
     pub fn print_tree(&self) {
         for entry in self.entries.iter() {
@@ -497,7 +1246,8 @@ impl Fst {
     }
 
     fn fix_ordering_rec(nodes: &mut Vec<FstNode>) {
-        nodes.sort_unstable_by(FstNode::node_compare);
+        // stable, so duplicates keep their order
+        nodes.sort_by(FstNode::node_compare);
         for node in nodes {
             match node {
                 FstNode::Directory { files, .. } => Self::fix_ordering_rec(files),
@@ -513,10 +1263,64 @@ pub struct FstToBytes {
     str_bytes: Vec<u8>,
 }
 
+/// Maximum length of a single name in shift-jis bytes
+pub const MAX_FST_NAME_LENGTH: usize = 255;
+
 #[derive(Error, Debug)]
 pub enum FstToBytesError {
     #[error("{0} can't be converted into shift-jis!")]
     InvalidShiftJis(String),
+    #[error("directory '{0}' contains a node without a name")]
+    EmptyName(String),
+    #[error("{0} contains a '/' or null character")]
+    InvalidName(String),
+    #[error("{0} is longer than {MAX_FST_NAME_LENGTH} bytes")]
+    NameTooLong(String),
+    #[error("{0} exists multiple times (ignoring case)")]
+    DuplicateName(String),
+    #[error("entries of directory '{0}' are not sorted, use Fst::fix_ordering")]
+    NotSorted(String),
+}
+
+/// Checks the invariants the console's lookup relies on: siblings are sorted
+/// case insensitive without duplicates and names are non empty without slashes
+fn check_names(nodes: &[FstNode], path: &mut Vec<String>) -> Result<(), FstToBytesError> {
+    let full_path = |path: &Vec<String>, name: &str| {
+        path.iter()
+            .map(String::as_str)
+            .chain(once(name))
+            .collect::<Vec<_>>()
+            .join("/")
+    };
+    for node in nodes {
+        let name = node.get_name();
+        if name.is_empty() {
+            return Err(FstToBytesError::EmptyName(path.join("/")));
+        }
+        if name.contains(['/', '\0']) {
+            return Err(FstToBytesError::InvalidName(full_path(path, name)));
+        }
+    }
+    for pair in nodes.windows(2) {
+        match pair[0].node_compare(&pair[1]) {
+            Ordering::Greater => return Err(FstToBytesError::NotSorted(path.join("/"))),
+            Ordering::Equal => {
+                return Err(FstToBytesError::DuplicateName(full_path(
+                    path,
+                    pair[1].get_name(),
+                )))
+            }
+            Ordering::Less => (),
+        }
+    }
+    for node in nodes {
+        if let FstNode::Directory { name, files } = node {
+            path.push(name.clone());
+            check_names(files, path)?;
+            path.pop();
+        }
+    }
+    Ok(())
 }
 
 fn rec_build_fst_bytes(
@@ -529,6 +1333,9 @@ fn rec_build_fst_bytes(
         if error {
             return Err(FstToBytesError::InvalidShiftJis(node.get_name().clone()));
         }
+        if bytes.len() > MAX_FST_NAME_LENGTH {
+            return Err(FstToBytesError::NameTooLong(node.get_name().clone()));
+        }
         let name_offset = str_bytes.len() as u32;
         str_bytes.extend_from_slice(bytes.as_ref());
         str_bytes.push(0);
@@ -544,10 +1351,26 @@ fn rec_build_fst_bytes(
 }
 
 impl TryFrom<Fst> for FstToBytes {
-    // TODO
     type Error = FstToBytesError;
 
+    /// Validates the names and ordering of all nodes, see [`FstToBytes::try_from_fix_ordering`]
+    /// to sort the nodes instead of failing
     fn try_from(value: Fst) -> Result<Self, Self::Error> {
+        check_names(&value.entries, &mut Vec::new())?;
+        Self::try_from_unchecked(value)
+    }
+}
+
+impl FstToBytes {
+    /// Sorts all nodes with [`Fst::fix_ordering`] before validating them
+    pub fn try_from_fix_ordering(mut value: Fst) -> Result<Self, FstToBytesError> {
+        value.fix_ordering();
+        Self::try_from(value)
+    }
+
+    /// Skips the checks of names and ordering, U8 archives are searched
+    /// linearly and don't need to be sorted
+    pub(crate) fn try_from_unchecked(value: Fst) -> Result<Self, FstToBytesError> {
         // buffers
         let mut str_offsets = Vec::new();
         let mut str_bytes = Vec::new();
@@ -565,6 +1388,38 @@ impl TryFrom<Fst> for FstToBytes {
 }
 
 impl FstToBytes {
+    /// Iterates over all files with their full path and mutable references to
+    /// their offset and length, in the order they appear in the fst
+    pub fn files_mut(&mut self) -> impl Iterator<Item = (String, &mut u64, &mut u32)> {
+        fn collect<'a>(
+            nodes: &'a mut [FstNode],
+            path: &mut Vec<String>,
+            out: &mut Vec<(String, &'a mut u64, &'a mut u32)>,
+        ) {
+            for node in nodes {
+                match node {
+                    FstNode::Directory { name, files } => {
+                        path.push(name.clone());
+                        collect(files, path, out);
+                        path.pop();
+                    }
+                    FstNode::File {
+                        name,
+                        offset,
+                        length,
+                    } => {
+                        path.push(name.clone());
+                        out.push((path.join("/"), offset, length));
+                        path.pop();
+                    }
+                }
+            }
+        }
+        let mut out = Vec::new();
+        collect(&mut self.fst.entries, &mut Vec::new(), &mut out);
+        out.into_iter()
+    }
+
     /// Allows to run a callback for all file nodes of this fst, which is given
     /// the full path up until that files
     /// this variant receives a mutable reference to file offset and length
@@ -610,6 +1465,11 @@ impl FstToBytes {
         Self::get_total_file_count_rec(&self.fst.entries)
     }
 
+    /// size of the node table and string table in bytes
+    pub(crate) fn get_byte_size(&self) -> usize {
+        self.str_offsets.len() * 12 + self.str_bytes.len()
+    }
+
     fn get_total_file_count_rec(nodes: &Vec<FstNode>) -> usize {
         nodes
             .iter()
@@ -621,6 +1481,15 @@ impl FstToBytes {
     }
 
     pub fn write_to<W: Write + Seek>(&self, w: &mut W) -> binrw::BinResult<()> {
+        self.write_to_with_shift(w, 2)
+    }
+
+    /// writes the FST, file offsets are shifted right by `offset_shift`
+    pub(crate) fn write_to_with_shift<W: Write + Seek>(
+        &self,
+        w: &mut W,
+        offset_shift: u32,
+    ) -> binrw::BinResult<()> {
         let mut raw_nodes = Vec::with_capacity(self.str_offsets.len());
         raw_nodes.push(RawFstNode {
             is_directory: true,
@@ -633,6 +1502,7 @@ impl FstToBytes {
             &self.fst.entries,
             &self.str_offsets,
             &mut raw_nodes,
+            offset_shift,
             &mut idx,
         );
         if let Some(node) = raw_nodes.get_mut(0) {
@@ -647,6 +1517,7 @@ impl FstToBytes {
         nodes: &Vec<FstNode>,
         str_offsets: &Vec<u32>,
         raw_nodes: &mut Vec<RawFstNode>,
+        offset_shift: u32,
         idx: &mut u32,
     ) {
         // the first non root node is 1, so this can't underflow
@@ -665,7 +1536,7 @@ impl FstToBytes {
                         offset: parent_idx,
                         length: u32::MAX,
                     });
-                    Self::build_node_bytes_rec(files, str_offsets, raw_nodes, idx);
+                    Self::build_node_bytes_rec(files, str_offsets, raw_nodes, offset_shift, idx);
                     // this index is always inbounds, but this way it doesn't introduce a panicking branch
                     if let Some(node) = raw_nodes.get_mut(this_idx) {
                         node.length = *idx;
@@ -675,7 +1546,7 @@ impl FstToBytes {
                     raw_nodes.push(RawFstNode {
                         is_directory: false,
                         name_offset,
-                        offset: (offset >> 2) as u32,
+                        offset: (offset >> offset_shift) as u32,
                         length,
                     });
                 }
@@ -690,7 +1561,10 @@ mod test {
 
     use crate::{Fst, FstNode};
 
-    use super::FstToBytes;
+    use super::{
+        FstError, FstGlob, FstReadError, FstToBytes, FstToBytesError, FstValidationError,
+        MAX_FST_DEPTH,
+    };
 
     fn get_test_fst() -> Fst {
         Fst {
@@ -739,7 +1613,19 @@ mod test {
         ));
         assert!(matches!(
             fst.add_node_path("file.arc", FstNode::create_file("test".into())),
-            Err(())
+            Err(FstError::NotADirectory(path)) if path == "file.arc"
+        ));
+        assert!(matches!(
+            fst.add_node_path("test/", FstNode::create_file("a/b".into())),
+            Err(FstError::InvalidName(_))
+        ));
+        assert!(matches!(
+            fst.add_node_path_no_replace("TEST/path5", FstNode::create_file("FILE.arc".into())),
+            Err(FstError::AlreadyExists(path)) if path == "TEST/path5/FILE.arc"
+        ));
+        assert!(matches!(
+            fst.add_node_path_no_replace("test/path6", FstNode::create_file("file.arc".into())),
+            Ok(())
         ));
 
         assert!(fst.find_node_path("file.arc").is_some());
@@ -753,19 +1639,24 @@ mod test {
         assert_eq!(test_dir_files[0].get_name(), "path");
         assert_eq!(test_dir_files[1].get_name(), "path2");
         assert_eq!(test_dir_files[2].get_name(), "path5");
+        assert_eq!(test_dir_files[3].get_name(), "path6");
     }
 
     #[test]
     pub fn test_remove() {
         let mut fst = get_test_fst();
         assert!(
-            matches!(fst.remove_node_path("directory/moar directories"), Some(FstNode::Directory { name, ..}) if name == "moar directories")
+            matches!(fst.remove_node_path("directory/moar directories"), Ok(FstNode::Directory { name, ..}) if name == "moar directories")
         );
         assert!(
-            matches!(fst.remove_node_path("directory"), Some(FstNode::Directory { name, ..}) if name == "directory")
+            matches!(fst.remove_node_path("directory"), Ok(FstNode::Directory { name, ..}) if name == "directory")
         );
         assert!(
-            matches!(fst.remove_node_path("file1"), Some(FstNode::File { name, ..}) if name == "file1")
+            matches!(fst.remove_node_path("file1"), Ok(FstNode::File { name, ..}) if name == "file1")
+        );
+        assert_eq!(
+            fst.remove_node_path("file1"),
+            Err(FstError::NotFound("file1".into()))
         );
     }
 
@@ -783,6 +1674,321 @@ mod test {
         );
     }
 
+    #[test]
+    pub fn test_glob() {
+        let mut fst = get_test_fst();
+        fst.add_node_path("Stage/F000", FstNode::create_file("F000_stg_l0.arc".into()))
+            .unwrap();
+        fst.add_node_path("Stage/F000", FstNode::create_file("F000.txt".into()))
+            .unwrap();
+        fst.add_node_path("Stage", FstNode::create_file("top.arc".into()))
+            .unwrap();
+        let paths: Vec<String> = fst.walk().map(|entry| entry.path).collect();
+        assert_eq!(paths[0], "directory");
+        assert_eq!(paths[1], "directory/moar directories");
+        assert_eq!(paths.len(), 9);
+
+        let glob = FstGlob::new("Stage/**/*.arc", false);
+        let matched: Vec<String> = fst.glob(&glob).map(|entry| entry.path).collect();
+        assert_eq!(matched, ["Stage/F000/F000_stg_l0.arc", "Stage/top.arc"]);
+        assert_eq!(fst.glob(&FstGlob::new("stage/**/*.ARC", false)).count(), 0);
+        assert_eq!(fst.glob(&FstGlob::new("stage/**/*.ARC", true)).count(), 2);
+        assert_eq!(fst.glob(&FstGlob::new("*/moar ?iles", false)).count(), 1);
+
+        assert!(fst.find_node_path("stage/f000").is_none());
+        assert!(fst.find_node_path_ignore_case("stage/f000").is_some());
+        assert_eq!(
+            fst.resolve_path_ignore_case("STAGE/f000/f000.TXT")
+                .as_deref(),
+            Some("Stage/F000/F000.txt")
+        );
+        assert!(fst.find_node_path_ignore_case("file1/x").is_none());
+
+        let removed = fst.remove_glob(&FstGlob::new("**/*.arc", false));
+        assert_eq!(removed, ["Stage/F000/F000_stg_l0.arc", "Stage/top.arc"]);
+        assert!(fst.find_node_path("Stage/F000/F000.txt").is_some());
+        // contents of matching directories are removed with them
+        assert_eq!(
+            fst.remove_glob(&FstGlob::new("**/F000*", false)),
+            ["Stage/F000"]
+        );
+        assert_eq!(fst.remove_glob(&FstGlob::new("Stage", false)), ["Stage"]);
+        assert!(fst.find_node_path("Stage").is_none());
+    }
+
+    #[test]
+    pub fn test_walk() {
+        let mut fst = get_test_fst();
+        let walked: Vec<(String, usize)> =
+            fst.walk().map(|entry| (entry.path, entry.depth)).collect();
+        assert_eq!(
+            walked,
+            [
+                ("directory".to_string(), 0),
+                ("directory/moar directories".to_string(), 1),
+                ("directory/moar files".to_string(), 1),
+                ("file1".to_string(), 0),
+            ]
+        );
+        let files: Vec<String> = fst.files().map(|entry| entry.path).collect();
+        assert_eq!(files, ["directory/moar files", "file1"]);
+        assert_eq!(fst.dirs().count(), 2);
+        assert_eq!(
+            fst.walk()
+                .take_while(|entry| entry.depth > 0 || entry.node.is_dir())
+                .count(),
+            3
+        );
+
+        let mut walker = fst.walk_mut();
+        let mut visited = Vec::new();
+        while let Some(entry) = walker.next() {
+            visited.push(entry.path);
+            if let FstNode::File { length, .. } = entry.node {
+                *length = 5;
+            }
+        }
+        assert_eq!(visited.len(), 4);
+        assert!(fst
+            .files()
+            .all(|entry| matches!(entry.node, FstNode::File { length: 5, .. })));
+
+        let mut walker = fst.walk_mut();
+        let first = walker.next().unwrap().path;
+        walker.skip_children();
+        assert_eq!(first, "directory");
+        assert_eq!(walker.next().unwrap().path, "file1");
+        assert!(walker.next().is_none());
+
+        let mut fst_to_bytes = FstToBytes::try_from(fst).unwrap();
+        for (i, (_, offset, _)) in fst_to_bytes.files_mut().enumerate() {
+            *offset = i as u64 * 0x20;
+        }
+        let offsets: Vec<(String, u64)> = fst_to_bytes
+            .files_mut()
+            .map(|(path, offset, _)| (path, *offset))
+            .collect();
+        assert_eq!(
+            offsets,
+            [
+                ("directory/moar files".to_string(), 0),
+                ("file1".to_string(), 0x20)
+            ]
+        );
+    }
+
+    fn file(name: &str, offset: u64, length: u32) -> FstNode {
+        FstNode::File {
+            name: name.into(),
+            offset,
+            length,
+        }
+    }
+
+    #[test]
+    pub fn test_validate() {
+        let fst = Fst {
+            entries: vec![
+                file("a", 0, 0x40),
+                file("b", 0x40, 0x40),
+                // same data as b
+                file("c", 0x40, 0x40),
+                FstNode::Directory {
+                    name: "d".into(),
+                    files: vec![file("e", 0x80, 0x20)],
+                },
+            ],
+        };
+        let mut bytes = Vec::new();
+        FstToBytes::try_from(fst)
+            .unwrap()
+            .write_to(&mut Cursor::new(&mut bytes))
+            .unwrap();
+        assert!(Fst::read_validated(&bytes, Some(0x100)).is_ok());
+        let Err(FstReadError::Invalid(errors)) = Fst::read_validated(&bytes, Some(0x60)) else {
+            panic!("out of bounds files not detected");
+        };
+        assert_eq!(errors.len(), 3);
+        assert!(
+            matches!(&errors[0], FstValidationError::FileOutOfBounds { path, .. } if path == "b")
+        );
+
+        // name offset of "a" outside of the string table
+        let mut broken = bytes.clone();
+        broken[12..16].copy_from_slice(&0xFFFFFFu32.to_be_bytes());
+        assert!(Fst::read_validated(&broken, None).is_err());
+        let (fst, errors) = Fst::read_repaired(&broken, None).unwrap();
+        assert_eq!(
+            errors[0],
+            FstValidationError::NameOutOfRange {
+                index: 1,
+                name_offset: 0xFFFFFF
+            }
+        );
+        assert!(fst.find_node_path("node_1").is_some());
+
+        // end of "d" past the end of the table
+        let mut broken = bytes.clone();
+        broken[4 * 12 + 8..4 * 12 + 12].copy_from_slice(&100u32.to_be_bytes());
+        assert!(Fst::read(&mut Cursor::new(&broken), 0).is_err());
+        let (fst, errors) = Fst::read_repaired(&broken, None).unwrap();
+        assert!(matches!(
+            errors[0],
+            FstValidationError::InvalidDirectoryEnd { end: 100, .. }
+        ));
+        assert!(fst.find_node_path("d/e").is_some());
+
+        let mut fst = Fst {
+            entries: vec![
+                file("b", 0, 0x40),
+                file("a", 0x20, 0x40),
+                file("B", 0x80, 0x40),
+            ],
+        };
+        let errors = fst.repair(Some(0xA0));
+        assert!(errors.contains(&FstValidationError::BadOrdering("".into())));
+        assert!(errors.contains(&FstValidationError::DuplicateName("B".into())));
+        assert!(errors.contains(&FstValidationError::OverlappingFiles {
+            first: "b".into(),
+            second: "a".into()
+        }));
+        assert_eq!(fst.entries, [file("a", 0x20, 0x40), file("b", 0, 0x40)]);
+        assert_eq!(fst.validate(Some(0xA0)).len(), 1);
+    }
+
+    #[test]
+    pub fn test_deep_nesting() {
+        // every directory is the only child of the previous one
+        let node_count = 100_000u32;
+        let mut bytes = Vec::new();
+        for index in 0..node_count {
+            bytes.extend_from_slice(&0x01000000u32.to_be_bytes());
+            bytes.extend_from_slice(&index.saturating_sub(1).to_be_bytes());
+            bytes.extend_from_slice(&node_count.to_be_bytes());
+        }
+        bytes.extend_from_slice(b"d\0");
+        let Err(FstReadError::Invalid(errors)) = Fst::read_validated(&bytes, None) else {
+            panic!("deep nesting not detected");
+        };
+        assert_eq!(
+            errors,
+            [FstValidationError::TooDeep(
+                vec!["d"; MAX_FST_DEPTH + 1].join("/")
+            )]
+        );
+        let (fst, _) = Fst::read_repaired(&bytes, None).unwrap();
+        assert_eq!(fst.dirs().count(), MAX_FST_DEPTH);
+
+        let mut files = vec![FstNode::create_file("f".into())];
+        for _ in 0..MAX_FST_DEPTH + 2 {
+            files = vec![FstNode::Directory {
+                name: "d".into(),
+                files,
+            }];
+        }
+        let mut fst = Fst { entries: files };
+        assert!(matches!(
+            fst.validate(None)[..],
+            [FstValidationError::TooDeep(_)]
+        ));
+        fst.repair(None);
+        assert!(fst.validate(None).is_empty());
+        assert_eq!(fst.dirs().count(), MAX_FST_DEPTH);
+    }
+
+    #[test]
+    pub fn test_to_bytes_checks() {
+        let unsorted = Fst {
+            entries: vec![
+                FstNode::Directory {
+                    name: "dir".into(),
+                    files: vec![file("b", 0, 0), file("A", 0, 0)],
+                },
+                file("e", 0, 0),
+            ],
+        };
+        assert!(matches!(
+            FstToBytes::try_from(unsorted.clone()),
+            Err(FstToBytesError::NotSorted(dir)) if dir == "dir"
+        ));
+        let mut fixed = FstToBytes::try_from_fix_ordering(unsorted).unwrap();
+        let paths: Vec<String> = fixed.files_mut().map(|(path, _, _)| path).collect();
+        assert_eq!(paths, ["dir/A", "dir/b", "e"]);
+
+        let check = |entries: Vec<FstNode>| FstToBytes::try_from(Fst { entries }).err();
+        assert!(matches!(
+            check(vec![file("a", 0, 0), file("A", 0, 0)]),
+            Some(FstToBytesError::DuplicateName(name)) if name == "A"
+        ));
+        assert!(matches!(
+            check(vec![file("", 0, 0)]),
+            Some(FstToBytesError::EmptyName(_))
+        ));
+        assert!(matches!(
+            check(vec![file("a/b", 0, 0)]),
+            Some(FstToBytesError::InvalidName(_))
+        ));
+        assert!(matches!(
+            check(vec![file(&"a".repeat(256), 0, 0)]),
+            Some(FstToBytesError::NameTooLong(_))
+        ));
+    }
+
+    #[test]
+    pub fn test_rename_move_copy() {
+        let mut fst = get_test_fst();
+        fst.rename_node_path("directory/moar files", "a file")
+            .unwrap();
+        let paths: Vec<String> = fst.walk().map(|entry| entry.path).collect();
+        assert_eq!(paths[1], "directory/a file");
+        fst.rename_node_path("file1", "FILE1").unwrap();
+        assert!(fst.find_node_path("FILE1").is_some());
+        assert_eq!(
+            fst.rename_node_path("FILE1", "Directory"),
+            Err(FstError::AlreadyExists("Directory".into()))
+        );
+        assert_eq!(
+            fst.rename_node_path("missing", "x"),
+            Err(FstError::NotFound("missing".into()))
+        );
+        assert_eq!(
+            fst.rename_node_path("FILE1", "a/b"),
+            Err(FstError::InvalidName("a/b".into()))
+        );
+
+        fst.move_node_path("directory", "US/files").unwrap();
+        assert!(fst.find_node_path("directory").is_none());
+        assert!(fst.find_node_path("US/files/moar directories").is_some());
+        assert_eq!(
+            fst.move_node_path("US", "US/files/US"),
+            Err(FstError::MoveIntoItself {
+                from: "US".into(),
+                to: "US/files/US".into()
+            })
+        );
+        assert_eq!(
+            fst.move_node_path("US/files", "FILE1/files"),
+            Err(FstError::NotADirectory("FILE1".into()))
+        );
+        // invalid parents are rejected before the node is removed
+        assert_eq!(
+            fst.move_node_path("FILE1", "new\0dir/FILE1"),
+            Err(FstError::InvalidName("new\0dir".into()))
+        );
+        assert!(fst.find_node_path("FILE1").is_some());
+
+        fst.copy_node_path("US", "EU").unwrap();
+        assert!(fst.find_node_path("EU/files/a file").is_some());
+        assert!(fst.find_node_path("US/files/a file").is_some());
+        assert_eq!(
+            fst.copy_node_path("US", "eu"),
+            Err(FstError::AlreadyExists("eu".into()))
+        );
+        let paths: Vec<String> = fst.walk().map(|entry| entry.path).collect();
+        assert_eq!(paths[0], "EU");
+        assert!(FstToBytes::try_from(fst).is_ok());
+    }
+
     #[test]
     pub fn test_build() {
         let fst = get_test_fst();
@@ -0,0 +1,236 @@
+use thiserror::Error;
+
+use crate::dol::{Dol, DolError, DolSectionKind};
+
+/// Start of the area the Gecko code handler normally uses, unused without a code handler
+pub const DEFAULT_CODE_CAVE_ADDRESS: u32 = 0x8000_1800;
+/// Size of the default code cave, it ends at 0x80003000
+pub const DEFAULT_CODE_CAVE_SIZE: u32 = 0x1800;
+
+const GCT_MAGIC: [u32; 2] = [0x00D0_C0DE, 0x00D0_C0DE];
+const GCT_END: [u32; 2] = [0xF000_0000, 0x0000_0000];
+
+#[derive(Error, Debug)]
+pub enum GeckoError {
+    #[error("dol error: {0}")]
+    Dol(#[from] DolError),
+    #[error("gct file has an invalid header or size")]
+    InvalidGct,
+    #[error("invalid code line: {0}")]
+    InvalidLine(String),
+    #[error("codetype {0:02X} is not supported")]
+    UnsupportedCodetype(u8),
+    #[error("C2 code at {0:#010x} is missing lines")]
+    TruncatedCode(u32),
+    #[error("code cave at {address:#010x} with size {size:#x} exceeds the address space")]
+    InvalidCodeCave { address: u32, size: u32 },
+    #[error("hooks need {needed:#x} bytes, but the code cave only has {size:#x}")]
+    CodeCaveTooSmall { needed: usize, size: u32 },
+}
+
+fn branch(from: u32, to: u32) -> u32 {
+    0x4800_0000 | (to.wrapping_sub(from) & 0x03FF_FFFC)
+}
+
+/// List of Gecko code lines, each line consists of 2 words
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GeckoCodes {
+    lines: Vec<[u32; 2]>,
+}
+
+impl GeckoCodes {
+    pub fn new(lines: Vec<[u32; 2]>) -> Self {
+        Self { lines }
+    }
+
+    pub fn get_lines(&self) -> &[[u32; 2]] {
+        &self.lines
+    }
+
+    /// Parses a .gct file, which has a header and a terminating line
+    pub fn from_gct(data: &[u8]) -> Result<Self, GeckoError> {
+        if !data.len().is_multiple_of(8) {
+            return Err(GeckoError::InvalidGct);
+        }
+        let mut lines: Vec<[u32; 2]> = data
+            .chunks_exact(8)
+            .map(|chunk| {
+                [
+                    u32::from_be_bytes(chunk[..4].try_into().unwrap()),
+                    u32::from_be_bytes(chunk[4..].try_into().unwrap()),
+                ]
+            })
+            .collect();
+        if lines.first() != Some(&GCT_MAGIC) {
+            return Err(GeckoError::InvalidGct);
+        }
+        lines.remove(0);
+        if let Some(end) = lines.iter().position(|line| *line == GCT_END) {
+            lines.truncate(end);
+        }
+        Ok(Self { lines })
+    }
+
+    /// Parses codes in text form, like in Dolphin's game ini files.
+    /// Only lines starting with 8 hex digits are code lines, names and comments are ignored
+    pub fn from_text(text: &str) -> Result<Self, GeckoError> {
+        let is_hex_word = |s: &str| s.len() == 8 && s.bytes().all(|b| b.is_ascii_hexdigit());
+        let mut lines = Vec::new();
+        for line in text.lines() {
+            let mut parts = line.split_whitespace();
+            match parts.next() {
+                Some(first) if is_hex_word(first) => {
+                    let second = parts
+                        .next()
+                        .filter(|second| is_hex_word(second) && parts.next().is_none())
+                        .ok_or_else(|| GeckoError::InvalidLine(line.to_string()))?;
+                    lines.push([
+                        u32::from_str_radix(first, 16).unwrap(),
+                        u32::from_str_radix(second, 16).unwrap(),
+                    ]);
+                }
+                _ => (),
+            }
+        }
+        Ok(Self { lines })
+    }
+
+    /// Applies the codes statically to the DOL. Supported are the direct write codetypes
+    /// 00, 02 and 04 and C2 hooks, the code of all hooks is added as a new text section at
+    /// `code_cave_address`, which fails if they don't fit in `code_cave_size` bytes
+    pub fn apply_to_dol(
+        &self,
+        dol: &mut Dol,
+        code_cave_address: u32,
+        code_cave_size: u32,
+    ) -> Result<(), GeckoError> {
+        if code_cave_address.checked_add(code_cave_size).is_none() {
+            return Err(GeckoError::InvalidCodeCave {
+                address: code_cave_address,
+                size: code_cave_size,
+            });
+        }
+        let mut code_cave: Vec<u8> = Vec::new();
+        let mut lines = self.lines.iter();
+        while let Some(&[first, second]) = lines.next() {
+            if [first, second] == GCT_END {
+                break;
+            }
+            // the lowest bit of the codetype is part of the address
+            let codetype = (first >> 24) as u8 & 0xFE;
+            let address = 0x8000_0000 | (first & 0x01FF_FFFF);
+            match codetype {
+                0x00 => {
+                    for i in 0..=(second >> 16) {
+                        dol.write_bytes(address + i, &[second as u8])?;
+                    }
+                }
+                0x02 => {
+                    for i in 0..=(second >> 16) {
+                        dol.write_bytes(address + i * 2, &(second as u16).to_be_bytes())?;
+                    }
+                }
+                0x04 => dol.write_u32(address, second)?,
+                0xC2 => {
+                    let line_count = second as usize;
+                    if line_count == 0 {
+                        return Err(GeckoError::TruncatedCode(address));
+                    }
+                    let hook_address = code_cave_address + code_cave.len() as u32;
+                    for _ in 0..line_count {
+                        let line = lines.next().ok_or(GeckoError::TruncatedCode(address))?;
+                        code_cave.extend_from_slice(&line[0].to_be_bytes());
+                        code_cave.extend_from_slice(&line[1].to_be_bytes());
+                    }
+                    if code_cave.len() > code_cave_size as usize {
+                        return Err(GeckoError::CodeCaveTooSmall {
+                            needed: code_cave.len(),
+                            size: code_cave_size,
+                        });
+                    }
+                    // the last word is reserved for the branch back
+                    let return_address = code_cave_address + code_cave.len() as u32 - 4;
+                    let return_offset = code_cave.len() - 4;
+                    code_cave[return_offset..]
+                        .copy_from_slice(&branch(return_address, address + 4).to_be_bytes());
+                    dol.write_u32(address, branch(address, hook_address))?;
+                }
+                _ => return Err(GeckoError::UnsupportedCodetype((first >> 24) as u8)),
+            }
+        }
+        if !code_cave.is_empty() {
+            dol.add_section(DolSectionKind::Text, code_cave_address, code_cave)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dol::{Dol, DolSectionKind};
+
+    use super::{GeckoCodes, GeckoError, DEFAULT_CODE_CAVE_ADDRESS, DEFAULT_CODE_CAVE_SIZE};
+
+    #[test]
+    pub fn test_apply_codes() {
+        let mut dol = Dol::new(0x80004000);
+        dol.add_section(DolSectionKind::Text, 0x80004000, vec![0; 0x100])
+            .unwrap();
+        let codes = GeckoCodes::from_text(
+            "$Test code\n\
+             04004000 11223344\n\
+             02004010 00014455\n\
+             00004020 00000066\n\
+             * comment\n\
+             C2004080 00000002\n\
+             38600001 60000000\n\
+             60000000 00000000\n",
+        )
+        .unwrap();
+        assert_eq!(codes.get_lines().len(), 6);
+        codes
+            .apply_to_dol(&mut dol, DEFAULT_CODE_CAVE_ADDRESS, DEFAULT_CODE_CAVE_SIZE)
+            .unwrap();
+        assert_eq!(dol.read_u32(0x80004000).unwrap(), 0x11223344);
+        assert_eq!(dol.read_u32(0x80004010).unwrap(), 0x44554455);
+        assert_eq!(dol.read_bytes(0x80004020, 2).unwrap(), &[0x66, 0]);
+        // branch to the code cave and back
+        assert_eq!(
+            dol.read_u32(0x80004080).unwrap(),
+            0x48000000 | (DEFAULT_CODE_CAVE_ADDRESS.wrapping_sub(0x80004080) & 0x03FFFFFC)
+        );
+        assert_eq!(dol.read_u32(DEFAULT_CODE_CAVE_ADDRESS).unwrap(), 0x38600001);
+        assert_eq!(
+            dol.read_u32(DEFAULT_CODE_CAVE_ADDRESS + 12).unwrap(),
+            0x48000000 | (0x80004084 - (DEFAULT_CODE_CAVE_ADDRESS + 12))
+        );
+
+        let mut gct = vec![0x00, 0xD0, 0xC0, 0xDE, 0x00, 0xD0, 0xC0, 0xDE];
+        gct.extend_from_slice(&[0x04, 0, 0x40, 0, 0, 0, 0, 1]);
+        gct.extend_from_slice(&[0xF0, 0, 0, 0, 0, 0, 0, 0]);
+        let codes = GeckoCodes::from_gct(&gct).unwrap();
+        assert_eq!(codes.get_lines(), &[[0x04004000, 1]]);
+        assert!(GeckoCodes::from_text("04004000 1122334").is_err());
+    }
+
+    #[test]
+    pub fn test_code_cave_size() {
+        let mut dol = Dol::new(0x80004000);
+        dol.add_section(DolSectionKind::Text, 0x80004000, vec![0; 0x100])
+            .unwrap();
+        let mut lines = vec![[0xC2004080, 0x301]];
+        lines.resize(0x302, [0x60000000, 0x60000000]);
+        let codes = GeckoCodes::new(lines);
+        assert!(matches!(
+            codes.apply_to_dol(&mut dol, DEFAULT_CODE_CAVE_ADDRESS, DEFAULT_CODE_CAVE_SIZE),
+            Err(GeckoError::CodeCaveTooSmall { needed: 0x1808, .. })
+        ));
+        assert!(matches!(
+            codes.apply_to_dol(&mut dol, 0xFFFFF000, DEFAULT_CODE_CAVE_SIZE),
+            Err(GeckoError::InvalidCodeCave { .. })
+        ));
+        codes
+            .apply_to_dol(&mut dol, DEFAULT_CODE_CAVE_ADDRESS, 0x1808)
+            .unwrap();
+    }
+}
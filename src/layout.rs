@@ -0,0 +1,185 @@
+use thiserror::Error;
+
+use crate::FstGlob;
+
+/// Alignment of files if no rule matches, the same as the original discs
+pub const DEFAULT_FILE_ALIGNMENT: u64 = 0x40;
+
+#[derive(Error, Debug)]
+pub enum LayoutError {
+    #[error("alignment has to be a power of 2 and at least 4: {0:#x}")]
+    InvalidAlignment(u64),
+    #[error("pinned file {path} has {new_length:#x} bytes, but only {original_length:#x} fit at its original offset")]
+    PinnedFileGrew {
+        path: String,
+        original_length: u32,
+        new_length: u64,
+    },
+    #[error("pinned file {path} at {offset:#x} overlaps data written up to {position:#x}")]
+    PinnedFileOverlap {
+        path: String,
+        offset: u64,
+        position: u64,
+    },
+}
+
+/// Controls where the builder places the data of files in a partition.
+///
+/// By default files are placed in FST order, aligned to [`DEFAULT_FILE_ALIGNMENT`].
+/// All rules use glob patterns (see [`FstGlob`]) on the full path, if multiple rules
+/// of the same kind match a file, the one added first is used
+#[derive(Debug, Clone)]
+pub struct LayoutPolicy {
+    default_alignment: u64,
+    alignments: Vec<(FstGlob, u64)>,
+    slack: Vec<(FstGlob, u64)>,
+    first: Vec<FstGlob>,
+    last: Vec<FstGlob>,
+    pinned: Vec<FstGlob>,
+    preserve_offsets: bool,
+}
+
+impl Default for LayoutPolicy {
+    fn default() -> Self {
+        Self {
+            default_alignment: DEFAULT_FILE_ALIGNMENT,
+            alignments: Vec::new(),
+            slack: Vec::new(),
+            first: Vec::new(),
+            last: Vec::new(),
+            pinned: Vec::new(),
+            preserve_offsets: false,
+        }
+    }
+}
+
+fn check_alignment(alignment: u64) -> Result<(), LayoutError> {
+    // file offsets are stored shifted right by 2
+    if !alignment.is_power_of_two() || alignment < 4 {
+        return Err(LayoutError::InvalidAlignment(alignment));
+    }
+    Ok(())
+}
+
+fn find_rule<'a, T>(rules: &'a [(FstGlob, T)], path: &str) -> Option<&'a T> {
+    rules
+        .iter()
+        .find(|(glob, _)| glob.matches_path(path))
+        .map(|(_, value)| value)
+}
+
+impl LayoutPolicy {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the alignment of files without a matching alignment rule
+    pub fn set_default_alignment(&mut self, alignment: u64) -> Result<(), LayoutError> {
+        check_alignment(alignment)?;
+        self.default_alignment = alignment;
+        Ok(())
+    }
+
+    /// Aligns the start of all matching files
+    pub fn add_alignment(&mut self, pattern: &str, alignment: u64) -> Result<(), LayoutError> {
+        check_alignment(alignment)?;
+        self.alignments
+            .push((FstGlob::new(pattern, false), alignment));
+        Ok(())
+    }
+
+    /// Reserves unused space after all matching files, so they can grow when
+    /// patching the built image in place
+    pub fn add_slack(&mut self, pattern: &str, bytes: u64) {
+        self.slack.push((FstGlob::new(pattern, false), bytes));
+    }
+
+    /// Places matching files before all other files, in the order of the rules
+    pub fn add_first(&mut self, pattern: &str) {
+        self.first.push(FstGlob::new(pattern, false));
+    }
+
+    /// Places matching files after all other files, in the order of the rules.
+    /// Files placed last are at the outer edge of the disc, where reading is the fastest
+    pub fn add_last(&mut self, pattern: &str) {
+        self.last.push(FstGlob::new(pattern, false));
+    }
+
+    /// Keeps matching files at their offset in the original partition, if the partition
+    /// definition knows it. Pinned files can't be larger than the original
+    pub fn add_pin_original(&mut self, pattern: &str) {
+        self.pinned.push(FstGlob::new(pattern, false));
+    }
+
+    /// Keeps every file at the offset it has in the FST of the partition definition,
+    /// like the offsets of the source ISO when copying a partition. Files that grew past
+    /// the start of the next file are moved after all others, new files fill the gaps.
+    /// Files pinned with [`LayoutPolicy::add_pin_original`] still fail when growing
+    pub fn set_preserve_offsets(&mut self, preserve_offsets: bool) {
+        self.preserve_offsets = preserve_offsets;
+    }
+
+    pub fn get_preserve_offsets(&self) -> bool {
+        self.preserve_offsets
+    }
+
+    pub(crate) fn get_alignment(&self, path: &str) -> u64 {
+        find_rule(&self.alignments, path)
+            .copied()
+            .unwrap_or(self.default_alignment)
+    }
+
+    pub(crate) fn get_slack(&self, path: &str) -> u64 {
+        find_rule(&self.slack, path).copied().unwrap_or(0)
+    }
+
+    pub(crate) fn is_pinned(&self, path: &str) -> bool {
+        self.pinned.iter().any(|glob| glob.matches_path(path))
+    }
+
+    /// Sorts files (given in FST order) into the order they are written
+    pub(crate) fn sort_files<T>(&self, files: &mut [(String, T)]) {
+        let rank = |path: &str| {
+            if let Some(idx) = self.first.iter().position(|glob| glob.matches_path(path)) {
+                (0, idx)
+            } else if let Some(idx) = self.last.iter().position(|glob| glob.matches_path(path)) {
+                (2, idx)
+            } else {
+                (1, 0)
+            }
+        };
+        // stable, so FST order is kept otherwise
+        files.sort_by_key(|(path, _)| rank(path));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LayoutPolicy;
+
+    #[test]
+    pub fn test_layout_policy() {
+        let mut policy = LayoutPolicy::new();
+        assert!(policy.add_alignment("*.thp", 3).is_err());
+        policy.add_alignment("**/*.thp", 0x8000).unwrap();
+        policy.add_slack("Stage/**", 0x1000);
+        policy.add_first("rels.arc");
+        policy.add_last("THP/**");
+        policy.add_last("Sound/**");
+        policy.add_pin_original("*.dat");
+        assert_eq!(policy.get_alignment("THP/Demo.thp"), 0x8000);
+        assert_eq!(policy.get_alignment("rels.arc"), 0x40);
+        assert_eq!(policy.get_slack("Stage/F000/F000.arc"), 0x1000);
+        assert_eq!(policy.get_slack("rels.arc"), 0);
+        assert!(policy.is_pinned("main.dat"));
+        assert!(!policy.is_pinned("Stage/main.dat"));
+
+        let mut files: Vec<(String, ())> = ["Sound/a", "THP/b", "a.arc", "rels.arc", "z.arc"]
+            .iter()
+            .map(|path| (path.to_string(), ()))
+            .collect();
+        policy.sort_files(&mut files);
+        let paths: Vec<&str> = files.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(paths, ["rels.arc", "a.arc", "z.arc", "THP/b", "Sound/a"]);
+    }
+}
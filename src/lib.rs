@@ -2,16 +2,32 @@ use std::ops::Deref;
 
 use binrw::binrw;
 
+pub mod banner;
 pub mod builder;
+pub mod compression;
 mod dir_reader;
+pub mod dol;
+pub mod elf;
+pub mod fakesign;
 mod fst;
+pub mod gecko;
+pub mod layout;
 mod reader_writer;
+pub mod rekey;
+pub mod rel;
+pub mod riivolution;
+pub mod signature;
 pub mod structs;
+pub mod tpl;
+pub mod u8;
 mod window;
 
 mod new_reader;
 
-pub use fst::{Fst, FstNode, FstToBytes};
+pub use fst::{
+    Fst, FstEntry, FstEntryMut, FstError, FstGlob, FstNode, FstReadError, FstToBytes,
+    FstToBytesError, FstValidationError, FstWalk, FstWalkerMut, MAX_FST_NAME_LENGTH,
+};
 pub use new_reader::{CryptPartReader, WiiIsoReader, WiiPartitionReadInfo};
 pub use window::IOWindow;
 
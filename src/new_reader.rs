@@ -11,9 +11,10 @@ use aes::{
 use binrw::{BinReaderExt, BinWriterExt};
 
 use crate::{
+    compression::{decompress_if_compressed, CompressionError},
     structs::{
-        read_parts, ApploaderHeader, Certificate, DOLHeader, DiscHeader, WiiPartTableEntry,
-        WiiPartType, WiiPartitionHeader, TMD,
+        read_parts, Apploader, ApploaderHeader, Bi2, Certificate, DOLHeader, DiscHeader,
+        PartitionDataMode, Region, WiiPartTableEntry, WiiPartType, WiiPartitionHeader, TMD,
     },
     Fst, FstNode, IOWindow, BLOCK_DATA_OFFSET, BLOCK_DATA_SIZE, BLOCK_SIZE, GROUP_DATA_SIZE,
     GROUP_SIZE,
@@ -25,7 +26,7 @@ pub struct WiiIsoReader<RS: Read + Seek> {
     pub file: RS,
     // TODO: proper structs
     header: DiscHeader,
-    region: [u8; 32],
+    region: Region,
     partitions: Vec<WiiPartTableEntry>,
 }
 
@@ -34,6 +35,7 @@ struct EncryptedPartState {
     // verification_h3: Option<Box<[u8; 0x18000]>>,
     data_offset: u64,
     encryption_key: [u8; 16],
+    data_mode: PartitionDataMode,
     // the current group loaded in the cache
     current_group: Option<u64>,
     // buffers the bytes for the current group, can be partially encrypted
@@ -50,6 +52,9 @@ impl EncryptedPartState {
         rs.seek(SeekFrom::Start(self.data_offset + group * GROUP_SIZE))?;
         rs.read_exact(self.group_cache.as_mut())?;
         self.current_group = Some(group);
+        if self.data_mode != PartitionDataMode::Encrypted {
+            return Ok(());
+        }
         // decrypt all blocks
         // TODO: it might be possible to optimize this but it introduces some complexity regarding writes
         // and decryption is *relatively* fast anyways
@@ -93,6 +98,15 @@ impl EncryptedPartState {
         buffer: &mut Vec<u8>,
     ) -> io::Result<()> {
         buffer.clear();
+        if self.data_mode == PartitionDataMode::Plain {
+            // no hashes, so data is contiguous
+            buffer.resize(
+                length.min(self.data_size.saturating_sub(offset)) as usize,
+                0,
+            );
+            rs.seek(SeekFrom::Start(self.data_offset + offset))?;
+            return rs.read_exact(buffer);
+        }
         buffer.reserve(length as usize);
         let mut group = offset / GROUP_DATA_SIZE;
         let mut block = (offset % GROUP_DATA_SIZE) / BLOCK_DATA_SIZE;
@@ -121,6 +135,14 @@ impl EncryptedPartState {
 
     // reads at most one group
     fn read_into<RS: Read + Seek>(&mut self, rs: &mut RS, mut buf: &mut [u8]) -> io::Result<usize> {
+        if self.data_mode == PartitionDataMode::Plain {
+            let bytes_left = self.data_size.saturating_sub(self.current_position);
+            let to_read = (buf.len() as u64).min(bytes_left) as usize;
+            rs.seek(SeekFrom::Start(self.data_offset + self.current_position))?;
+            let read_bytes = rs.read(&mut buf[..to_read])?;
+            self.current_position += read_bytes as u64;
+            return Ok(read_bytes);
+        }
         let group = self.current_position / GROUP_DATA_SIZE;
         let mut block = (self.current_position % GROUP_DATA_SIZE) / BLOCK_DATA_SIZE;
         let mut offset_in_block_data = self.current_position % BLOCK_DATA_SIZE;
@@ -226,6 +248,25 @@ impl WiiPartitionReadInfo {
         &self.fst
     }
 
+    /// Size of the partition data, all files have to be inside of it
+    pub fn get_data_size(&self) -> u64 {
+        self.encrypt_part_state.data_size
+    }
+
+    /// Reads the raw FST, for validating or repairing it with
+    /// [`Fst::read_validated`] or [`Fst::read_repaired`]
+    pub fn read_fst_bytes<RS: Read + Seek>(
+        &mut self,
+        reader: &mut WiiIsoReader<RS>,
+    ) -> binrw::BinResult<Vec<u8>> {
+        let mut fst_buf = Vec::new();
+        let fst_off = *self.encrypted_header.fst_off;
+        let fst_sz = *self.encrypted_header.fst_sz;
+        self.get_crypto_reader(reader)
+            .read_into_vec(fst_off, fst_sz, &mut fst_buf)?;
+        Ok(fst_buf)
+    }
+
     pub fn read_tmd<RS: Read + Seek>(
         &mut self,
         reader: &mut WiiIsoReader<RS>,
@@ -277,6 +318,19 @@ impl WiiPartitionReadInfo {
         Some(self.open_window(reader, offset, Some(length)))
     }
 
+    /// Reads the entire file, Yaz0 and Yay0 compressed files get decompressed
+    pub fn read_file_decompressed<RS: Read + Seek>(
+        &mut self,
+        reader: &mut WiiIsoReader<RS>,
+        path: &str,
+    ) -> Option<Result<Vec<u8>, CompressionError>> {
+        let mut buf = Vec::new();
+        if let Err(e) = self.open_file(reader, path)?.read_to_end(&mut buf) {
+            return Some(Err(e.into()));
+        }
+        Some(decompress_if_compressed(buf))
+    }
+
     pub fn read_bi2<RS: Read + Seek>(
         &mut self,
         reader: &mut WiiIsoReader<RS>,
@@ -301,6 +355,24 @@ impl WiiPartitionReadInfo {
         Ok(buf)
     }
 
+    pub fn read_parsed_bi2<RS: Read + Seek>(
+        &mut self,
+        reader: &mut WiiIsoReader<RS>,
+    ) -> binrw::BinResult<Bi2> {
+        let mut crypt_part_reader = self.get_crypto_reader(reader);
+        crypt_part_reader.seek(SeekFrom::Start(0x440))?;
+        crypt_part_reader.read_be()
+    }
+
+    pub fn read_parsed_apploader<RS: Read + Seek>(
+        &mut self,
+        reader: &mut WiiIsoReader<RS>,
+    ) -> binrw::BinResult<Apploader> {
+        let mut crypt_part_reader = self.get_crypto_reader(reader);
+        crypt_part_reader.seek(SeekFrom::Start(0x2440))?;
+        crypt_part_reader.read_be()
+    }
+
     pub fn read_dol<RS: Read + Seek>(
         &mut self,
         reader: &mut WiiIsoReader<RS>,
@@ -352,12 +424,7 @@ impl WiiPartitionReadInfo {
         write_file(&sys_folder, "bi2.bin", &self.read_bi2(reader)?)?;
         write_file(&sys_folder, "apploader.img", &self.read_apploader(reader)?)?;
         write_file(&sys_folder, "main.dol", &self.read_dol(reader)?)?;
-        let mut fst_buf = Vec::new();
-        let fst_off = *self.encrypted_header.fst_off;
-        let fst_sz = *self.encrypted_header.fst_sz;
-        self.get_crypto_reader(reader)
-            .read_into_vec(fst_off, fst_sz, &mut fst_buf)?;
-        write_file(&sys_folder, "fst.bin", &fst_buf)?;
+        write_file(&sys_folder, "fst.bin", &self.read_fst_bytes(reader)?)?;
         Ok(())
     }
 }
@@ -367,9 +434,8 @@ impl<RS: Read + Seek> WiiIsoReader<RS> {
         rs.seek(SeekFrom::Start(0))?;
         let header: DiscHeader = rs.read_be()?;
         let partitions = read_parts(&mut rs)?;
-        let mut region = [0u8; 32];
         rs.seek(SeekFrom::Start(0x4E000))?;
-        rs.read_exact(&mut region)?;
+        let region: Region = rs.read_be()?;
         Ok(WiiIsoReader {
             file: rs,
             header,
@@ -386,7 +452,7 @@ impl<RS: Read + Seek> WiiIsoReader<RS> {
         &self.header
     }
 
-    pub fn get_region(&self) -> &[u8; 32] {
+    pub fn get_region(&self) -> &Region {
         &self.region
     }
 
@@ -404,8 +470,13 @@ impl<RS: Read + Seek> WiiIsoReader<RS> {
             current_position: 0,
             data_offset: partition.get_offset() + *wii_partition_header.data_off,
             encryption_key: wii_partition_header.ticket.title_key.clone(),
+            data_mode: self.header.get_partition_data_mode(),
             data_size: *wii_partition_header.data_size,
-            group_cache: Box::new([0; GROUP_SIZE as usize]),
+            // allocated on the heap directly, the array would overflow the stack in debug builds
+            group_cache: vec![0; GROUP_SIZE as usize]
+                .into_boxed_slice()
+                .try_into()
+                .unwrap(),
         };
 
         let mut crypt_reader = CryptPartReader {
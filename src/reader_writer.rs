@@ -40,7 +40,8 @@ impl OpenMode {
 pub struct WiiEncryptedReadWriteStreamInner {
     h3: Option<Box<[u8; 0x18000]>>,
     data_offset: u64,
-    encryption_key: [u8; 16],
+    // blocks are only hashed but not encrypted without a key
+    encryption_key: Option<[u8; 16]>,
     open_mode: OpenMode,
     // the current group loaded in the cache
     current_group: Option<u64>,
@@ -73,7 +74,7 @@ pub struct WiiEncryptedReadWriteStream<'a, RS: Read + Seek> {
 fn hash_encrypt_block(
     buffer: &mut [u8; 0x200000],
     h3_ref: Option<&mut [u8; 20]>,
-    encryption_key: &[u8; 16],
+    encryption_key: Option<&[u8; 16]>,
 ) {
     // hash the entire block using nintendos complicated algorithm
     // https://github.com/AxioDL/nod/blob/b513a7f4e02d1b2a0c4563af73ba261d6760ab0e/lib/DiscWii.cpp#L625
@@ -114,6 +115,9 @@ fn hash_encrypt_block(
             let ptr0 = &mut ptr1[c * 0x8000..];
             ptr0[0x340..][..h2.len()].copy_from_slice(&h2);
             ptr0[0x3E0..][..0x20].copy_from_slice(&[0; 0x20]);
+            let Some(encryption_key) = encryption_key else {
+                continue;
+            };
             Aes128CbcEnc::new(encryption_key.into(), [0; 16].as_ref().into())
                 .encrypt_padded_mut::<NoPadding>(&mut ptr0[..0x400], 0x400)
                 // TODO: can bad data cause a panic here?
@@ -207,6 +211,11 @@ fn decrypt_verify_group(
 }
 
 impl<'a, RS: Read + Seek> WiiEncryptedReadWriteStream<'a, RS> {
+    /// releases the file, the state can be reattached to it with [`WiiEncryptedReadWriteStreamInner::with_file`]
+    pub fn into_inner(self) -> WiiEncryptedReadWriteStreamInner {
+        self.inner
+    }
+
     pub fn take_h3(&mut self) -> Option<Box<[u8; 0x18000]>> {
         self.inner.h3.take()
     }
@@ -214,7 +223,7 @@ impl<'a, RS: Read + Seek> WiiEncryptedReadWriteStream<'a, RS> {
     pub fn create_readonly(
         file: &'a mut RS,
         data_offset: u64,
-        encryption_key: [u8; 16],
+        encryption_key: Option<[u8; 16]>,
         max_group: u64,
     ) -> Self {
         // let group_cache = Box::new([0; GROUP_SIZE as usize]);
@@ -245,6 +254,9 @@ impl<'a, RS: Read + Seek> WiiEncryptedReadWriteStream<'a, RS> {
             .seek(SeekFrom::Start(self.inner.data_offset + group * GROUP_SIZE))?;
         self.file.read_exact(self.inner.group_cache.as_mut())?;
         self.inner.current_group = Some(group);
+        let Some(encryption_key) = self.inner.encryption_key else {
+            return Ok(());
+        };
         // decrypt all blocks
         // TODO: it might be possible to optimize this but it introduces some complexity regarding writes
         // and decryption is *relatively* fast anyways
@@ -252,7 +264,7 @@ impl<'a, RS: Read + Seek> WiiEncryptedReadWriteStream<'a, RS> {
             let block_data =
                 &mut self.inner.group_cache[(block * BLOCK_SIZE) as usize..][..BLOCK_SIZE as usize];
             let crypto = Aes128CbcDec::new(
-                self.inner.encryption_key.as_ref().into(),
+                encryption_key.as_ref().into(),
                 block_data[0x3d0..][..0x10].as_ref().into(),
             );
             crypto
@@ -316,7 +328,7 @@ impl<'a, RS: Write + Read + Seek> WiiEncryptedReadWriteStream<'a, RS> {
     pub fn create_write(
         file: &'a mut RS,
         data_offset: u64,
-        encryption_key: [u8; 16],
+        encryption_key: Option<[u8; 16]>,
         max_group: Option<u64>,
         filled_groups: u64,
     ) -> Self {
@@ -401,7 +413,7 @@ impl<'a, WS: Write + Read + Seek> Write for WiiEncryptedReadWriteStream<'a, WS>
                                             .try_into()
                                             .unwrap()
                                     }),
-                                    &self.inner.encryption_key,
+                                    self.inner.encryption_key.as_ref(),
                                 );
                                 self.file.seek(SeekFrom::Start(
                                     self.inner.data_offset + GROUP_SIZE * current_group,
@@ -460,7 +472,7 @@ impl<'a, WS: Write + Read + Seek> Write for WiiEncryptedReadWriteStream<'a, WS>
                                     .try_into()
                                     .unwrap()
                             }),
-                            &self.inner.encryption_key,
+                            self.inner.encryption_key.as_ref(),
                         );
                         self.file.seek(SeekFrom::Start(
                             self.inner.data_offset + GROUP_SIZE * current_group,
@@ -513,7 +525,7 @@ mod test {
         let mut encrypt_write = WiiEncryptedReadWriteStream::create_write(
             &mut cur,
             0,
-            [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+            Some([0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]),
             None,
             0,
         );
@@ -530,7 +542,8 @@ mod test {
         encrypt_write
             .read_into_vec(0, GROUP_DATA_SIZE + 0x1000, &mut data)
             .unwrap();
-        let mut outf = File::create("test.bin").unwrap();
+        let mut outf =
+            File::create(std::env::temp_dir().join("disc_riider_test_write.bin")).unwrap();
         outf.write_all(&data).unwrap();
         drop(outf);
         for i in &data[0..200] {
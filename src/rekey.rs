@@ -0,0 +1,171 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use binrw::BinWriterExt;
+use thiserror::Error;
+
+use crate::{
+    fakesign::{fakesign_ticket, FakesignError},
+    reader_writer::WiiEncryptedReadWriteStream,
+    structs::{InvalidCommonKeyIdx, PartitionDataMode, WiiPartTableEntry},
+    WiiIsoReader, COMMON_KEYS, GROUP_DATA_SIZE,
+};
+
+#[derive(Error, Debug)]
+pub enum RekeyError {
+    #[error("io error: {0}")]
+    IO(#[from] io::Error),
+    #[error("binrw error: {0}")]
+    BinRW(#[from] binrw::Error),
+    #[error("{0}")]
+    InvalidCommonKeyIdx(#[from] InvalidCommonKeyIdx),
+    #[error("fakesigning failed: {0}")]
+    Fakesign(#[from] FakesignError),
+    #[error("partition data is not encrypted")]
+    NotEncrypted,
+}
+
+/// Re-encrypts a partition in place with a different title key and/or common key index,
+/// for example to convert a disc using the korean common key to the normal one.
+///
+/// The data only has to be re-encrypted if the title key changes, otherwise only the ticket is
+/// rewritten. The ticket is fakesigned afterwards.
+pub fn rekey_partition<RS, C>(
+    reader: &mut WiiIsoReader<RS>,
+    partition: WiiPartTableEntry,
+    title_key: Option<[u8; 16]>,
+    common_key_idx: Option<u8>,
+    progress_cb: &mut C,
+) -> Result<(), RekeyError>
+where
+    RS: Read + Write + Seek,
+    C: FnMut(u8),
+{
+    progress_cb(0);
+    if reader.get_header().get_partition_data_mode() != PartitionDataMode::Encrypted {
+        return Err(RekeyError::NotEncrypted);
+    }
+    if let Some(idx) = common_key_idx {
+        if idx as usize >= COMMON_KEYS.len() {
+            return Err(InvalidCommonKeyIdx(idx).into());
+        }
+    }
+    let mut part_info = reader.open_partition(partition.clone())?;
+    let part_header = part_info.get_partition_header().clone();
+    let mut ticket = part_header.ticket.clone();
+
+    if let Some(title_key) = title_key.filter(|key| *key != ticket.title_key) {
+        // the H3 table has an entry for every group that is in use
+        let mut h3 = vec![0; 0x18000];
+        reader.file.seek(SeekFrom::Start(
+            partition.get_offset() + *part_header.global_hash_table_off,
+        ))?;
+        reader.file.read_exact(&mut h3)?;
+        let max_groups = part_header.data_size.div_ceil(GROUP_DATA_SIZE) as usize;
+        let groups = h3
+            .chunks_exact(20)
+            .take(max_groups)
+            .rposition(|hash| hash.iter().any(|b| *b != 0))
+            .map_or(0, |last| last as u64 + 1);
+
+        let mut writer_state = WiiEncryptedReadWriteStream::create_write(
+            &mut reader.file,
+            partition.get_offset() + *part_header.data_off,
+            Some(title_key),
+            None,
+            0,
+        )
+        .into_inner();
+        let mut buf = Vec::with_capacity(GROUP_DATA_SIZE as usize);
+        for group in 0..groups {
+            // always process entire groups, the group gets decrypted with the old key
+            // before the new one overwrites it
+            part_info.get_crypto_reader(reader).read_into_vec(
+                group * GROUP_DATA_SIZE,
+                GROUP_DATA_SIZE,
+                &mut buf,
+            )?;
+            buf.resize(GROUP_DATA_SIZE as usize, 0);
+            let mut writer = writer_state.with_file(&mut reader.file);
+            writer.seek(SeekFrom::Start(group * GROUP_DATA_SIZE))?;
+            writer.write_all(&buf)?;
+            writer.flush()?;
+            writer_state = writer.into_inner();
+            progress_cb(((group + 1) as f64 / groups as f64 * 100f64) as u8);
+        }
+        ticket.title_key = title_key;
+    }
+    if let Some(idx) = common_key_idx {
+        ticket.common_key_idx = idx;
+    }
+    if ticket != part_header.ticket {
+        fakesign_ticket(&mut ticket)?;
+        // the ticket is the first part of the partition header, the title key gets encrypted
+        // with the common key when writing it
+        reader.file.seek(SeekFrom::Start(partition.get_offset()))?;
+        reader.file.write_be(&ticket)?;
+        reader.file.flush()?;
+    }
+    progress_cb(100);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use crate::{
+        builder::test::{build, read_file, TestPartition},
+        structs::PartitionDataMode,
+        WiiIsoReader,
+    };
+
+    use super::{rekey_partition, RekeyError};
+
+    #[test]
+    pub fn test_rekey_partition() {
+        let files = || vec![("a.bin", (0..0x50000u32).map(|i| i as u8).collect())];
+        let disc = build(&mut TestPartition::new(files()), |builder| {
+            builder
+                .set_partition_data_mode(PartitionDataMode::Encrypted)
+                .unwrap()
+        })
+        .unwrap();
+        let mut reader = WiiIsoReader::open(Cursor::new(disc)).unwrap();
+        let partition = reader.partitions()[0].clone();
+        assert!(matches!(
+            rekey_partition(&mut reader, partition.clone(), None, Some(2), &mut |_| {}),
+            Err(RekeyError::InvalidCommonKeyIdx(_))
+        ));
+        rekey_partition(
+            &mut reader,
+            partition.clone(),
+            Some([9; 16]),
+            Some(1),
+            &mut |_| {},
+        )
+        .unwrap();
+
+        let mut reader = WiiIsoReader::open(reader.file).unwrap();
+        let info = reader.open_partition(partition).unwrap();
+        let ticket = &info.get_partition_header().ticket;
+        assert_eq!(ticket.title_key, [9; 16]);
+        assert_eq!(ticket.common_key_idx, 1);
+        let disc = reader.file.into_inner();
+        assert_eq!(read_file(disc, "a.bin"), files()[0].1);
+    }
+
+    #[test]
+    pub fn test_rekey_not_encrypted() {
+        let disc = build(
+            &mut TestPartition::new(vec![("a.bin", vec![1; 0x10])]),
+            |_| {},
+        )
+        .unwrap();
+        let mut reader = WiiIsoReader::open(Cursor::new(disc)).unwrap();
+        let partition = reader.partitions()[0].clone();
+        assert!(matches!(
+            rekey_partition(&mut reader, partition, Some([9; 16]), None, &mut |_| {}),
+            Err(RekeyError::NotEncrypted)
+        ));
+    }
+}
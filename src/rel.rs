@@ -0,0 +1,498 @@
+use std::io::{self, Cursor, Seek, SeekFrom};
+
+use binrw::{binrw, BinReaderExt};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum RelError {
+    #[error("io error: {0}")]
+    IO(#[from] io::Error),
+    #[error("binrw error: {0}")]
+    BinRW(#[from] binrw::Error),
+    #[error("section {0} doesn't exist or has no data")]
+    InvalidSection(u8),
+    #[error("section {section} data is out of bounds")]
+    SectionOutOfBounds { section: u8 },
+    #[error("range at {offset:#x} with length {length:#x} is out of bounds for section {section}")]
+    OutOfBounds {
+        section: u8,
+        offset: u32,
+        length: usize,
+    },
+    #[error("relocation type {0} is not supported")]
+    UnsupportedRelocation(u8),
+}
+
+#[binrw]
+#[brw(big)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RelHeader {
+    pub id: u32,
+    pub next: u32,
+    pub prev: u32,
+    pub num_sections: u32,
+    pub section_info_off: u32,
+    pub name_off: u32,
+    pub name_size: u32,
+    pub version: u32,
+    pub bss_size: u32,
+    pub rel_off: u32,
+    pub imp_off: u32,
+    pub imp_size: u32,
+    pub prolog_section: u8,
+    pub epilog_section: u8,
+    pub unresolved_section: u8,
+    pub bss_section: u8,
+    pub prolog: u32,
+    pub epilog: u32,
+    pub unresolved: u32,
+    /// only present since version 2
+    #[br(if(version >= 2))]
+    #[bw(if(*version >= 2))]
+    pub align: u32,
+    #[br(if(version >= 2))]
+    #[bw(if(*version >= 2))]
+    pub bss_align: u32,
+    /// only present since version 3
+    #[br(if(version >= 3))]
+    #[bw(if(*version >= 3))]
+    pub fix_size: u32,
+}
+
+#[binrw]
+#[brw(big)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RelSectionInfo {
+    /// lowest bit marks executable sections
+    offset_and_flag: u32,
+    pub size: u32,
+}
+
+impl RelSectionInfo {
+    pub fn offset(&self) -> u32 {
+        self.offset_and_flag & !1
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.offset_and_flag & 1 != 0
+    }
+
+    /// bss sections have a size but no data in the file
+    pub fn is_bss(&self) -> bool {
+        self.offset() == 0 && self.size != 0
+    }
+}
+
+#[binrw]
+#[brw(big)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct RawImport {
+    module_id: u32,
+    offset: u32,
+}
+
+#[binrw]
+#[brw(big)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct RawRelocation {
+    /// offset from the previous relocation
+    offset: u16,
+    reloc_type: u8,
+    section: u8,
+    addend: u32,
+}
+
+const R_DOLPHIN_NOP: u8 = 201;
+const R_DOLPHIN_SECTION: u8 = 202;
+const R_DOLPHIN_END: u8 = 203;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RelocationType {
+    None,
+    Addr32,
+    Addr24,
+    Addr16,
+    Addr16Lo,
+    Addr16Hi,
+    Addr16Ha,
+    Addr14,
+    Rel24,
+    Rel14,
+    Other(u8),
+}
+
+impl From<u8> for RelocationType {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Self::None,
+            1 => Self::Addr32,
+            2 => Self::Addr24,
+            3 => Self::Addr16,
+            4 => Self::Addr16Lo,
+            5 => Self::Addr16Hi,
+            6 => Self::Addr16Ha,
+            7..=9 => Self::Addr14,
+            10 => Self::Rel24,
+            11..=13 => Self::Rel14,
+            other => Self::Other(other),
+        }
+    }
+}
+
+/// Relocation with the absolute offset into the section it patches
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RelRelocation {
+    /// section that gets patched
+    pub section: u8,
+    /// offset into the patched section
+    pub offset: u32,
+    pub reloc_type: RelocationType,
+    /// section of the target module the symbol is in, unused for the DOL
+    pub target_section: u8,
+    /// offset of the symbol in the target section, absolute address for the DOL
+    pub addend: u32,
+}
+
+/// Relocations against a single module, module 0 is the DOL
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RelImport {
+    pub module_id: u32,
+    pub relocations: Vec<RelRelocation>,
+}
+
+/// Relocatable module, sections can be patched in place
+#[derive(Clone, Debug)]
+pub struct Rel {
+    data: Vec<u8>,
+    header: RelHeader,
+    sections: Vec<RelSectionInfo>,
+    imports: Vec<RelImport>,
+}
+
+fn read_relocations(
+    cursor: &mut Cursor<&[u8]>,
+    offset: u32,
+) -> binrw::BinResult<Vec<RelRelocation>> {
+    cursor.seek(SeekFrom::Start(offset as u64))?;
+    let mut relocations = Vec::new();
+    let mut section = 0;
+    let mut section_offset = 0u32;
+    loop {
+        let raw: RawRelocation = cursor.read_be()?;
+        match raw.reloc_type {
+            R_DOLPHIN_END => break,
+            R_DOLPHIN_SECTION => {
+                section = raw.section;
+                section_offset = 0;
+            }
+            // saturating, a corrupt file can't make the offset wrap around
+            R_DOLPHIN_NOP => section_offset = section_offset.saturating_add(raw.offset as u32),
+            reloc_type => {
+                section_offset = section_offset.saturating_add(raw.offset as u32);
+                relocations.push(RelRelocation {
+                    section,
+                    offset: section_offset,
+                    reloc_type: reloc_type.into(),
+                    target_section: raw.section,
+                    addend: raw.addend,
+                });
+            }
+        }
+    }
+    Ok(relocations)
+}
+
+impl Rel {
+    pub fn read(data: Vec<u8>) -> Result<Self, RelError> {
+        let mut cursor = Cursor::new(data.as_slice());
+        let header: RelHeader = cursor.read_be()?;
+        cursor.seek(SeekFrom::Start(header.section_info_off as u64))?;
+        // the section count comes from the file, so it's not used to preallocate
+        let mut sections = Vec::new();
+        for idx in 0..header.num_sections {
+            let section: RelSectionInfo = cursor.read_be()?;
+            if !section.is_bss() && section.offset() as usize + section.size as usize > data.len() {
+                return Err(RelError::SectionOutOfBounds { section: idx as u8 });
+            }
+            sections.push(section);
+        }
+        cursor.seek(SeekFrom::Start(header.imp_off as u64))?;
+        let mut raw_imports = Vec::new();
+        for _ in 0..header.imp_size / 8 {
+            raw_imports.push(cursor.read_be::<RawImport>()?);
+        }
+        let imports = raw_imports
+            .into_iter()
+            .map(|import| {
+                Ok(RelImport {
+                    module_id: import.module_id,
+                    relocations: read_relocations(&mut cursor, import.offset)?,
+                })
+            })
+            .collect::<binrw::BinResult<_>>()?;
+        Ok(Self {
+            data,
+            header,
+            sections,
+            imports,
+        })
+    }
+
+    pub fn get_header(&self) -> &RelHeader {
+        &self.header
+    }
+
+    pub fn get_sections(&self) -> &[RelSectionInfo] {
+        &self.sections
+    }
+
+    pub fn get_imports(&self) -> &[RelImport] {
+        &self.imports
+    }
+
+    /// Returns the module name from the string table, if it's part of the file
+    pub fn get_name(&self) -> Option<&[u8]> {
+        self.data
+            .get(self.header.name_off as usize..)?
+            .get(..self.header.name_size as usize)
+    }
+
+    /// Returns all relocations that patch bytes in the given range of a section,
+    /// useful to check that a patch doesn't overwrite relocated instructions
+    pub fn relocations_in_range(
+        &self,
+        section: u8,
+        offset: u32,
+        length: u32,
+    ) -> impl Iterator<Item = (u32, &RelRelocation)> {
+        self.imports.iter().flat_map(move |import| {
+            import
+                .relocations
+                .iter()
+                .filter(move |reloc| {
+                    // relocations patch up to 4 bytes
+                    reloc.section == section
+                        && reloc.offset < offset.saturating_add(length)
+                        && reloc.offset.saturating_add(4) > offset
+                })
+                .map(move |reloc| (import.module_id, reloc))
+        })
+    }
+
+    fn section_range(&self, section: u8, offset: u32, length: usize) -> Result<usize, RelError> {
+        let info = self
+            .sections
+            .get(section as usize)
+            .filter(|info| info.offset() != 0)
+            .ok_or(RelError::InvalidSection(section))?;
+        if offset as usize + length > info.size as usize {
+            return Err(RelError::OutOfBounds {
+                section,
+                offset,
+                length,
+            });
+        }
+        Ok(info.offset() as usize + offset as usize)
+    }
+
+    pub fn get_section_data(&self, section: u8) -> Result<&[u8], RelError> {
+        let size = self
+            .sections
+            .get(section as usize)
+            .ok_or(RelError::InvalidSection(section))?
+            .size as usize;
+        let start = self.section_range(section, 0, size)?;
+        Ok(&self.data[start..start + size])
+    }
+
+    pub fn read_bytes(&self, section: u8, offset: u32, length: usize) -> Result<&[u8], RelError> {
+        let start = self.section_range(section, offset, length)?;
+        Ok(&self.data[start..start + length])
+    }
+
+    pub fn read_u32(&self, section: u8, offset: u32) -> Result<u32, RelError> {
+        let bytes = self.read_bytes(section, offset, 4)?;
+        Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// Overwrites bytes at an offset into a section
+    pub fn write_bytes(&mut self, section: u8, offset: u32, data: &[u8]) -> Result<(), RelError> {
+        let start = self.section_range(section, offset, data.len())?;
+        self.data[start..start + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    pub fn write_u32(&mut self, section: u8, offset: u32, value: u32) -> Result<(), RelError> {
+        self.write_bytes(section, offset, &value.to_be_bytes())
+    }
+
+    /// Applies a relocation, `symbol_address` is the resolved address of the target symbol
+    /// including the addend, `site_address` is the address the patched section offset is
+    /// loaded to
+    pub fn apply_relocation(
+        &mut self,
+        relocation: &RelRelocation,
+        symbol_address: u32,
+        site_address: u32,
+    ) -> Result<(), RelError> {
+        let (section, offset) = (relocation.section, relocation.offset);
+        let s = symbol_address;
+        match relocation.reloc_type {
+            RelocationType::None => Ok(()),
+            RelocationType::Addr32 => self.write_u32(section, offset, s),
+            RelocationType::Addr16 | RelocationType::Addr16Lo => {
+                self.write_bytes(section, offset, &(s as u16).to_be_bytes())
+            }
+            RelocationType::Addr16Hi => {
+                self.write_bytes(section, offset, &((s >> 16) as u16).to_be_bytes())
+            }
+            RelocationType::Addr16Ha => self.write_bytes(
+                section,
+                offset,
+                &((s.wrapping_add(0x8000) >> 16) as u16).to_be_bytes(),
+            ),
+            RelocationType::Addr24 => {
+                let orig = self.read_u32(section, offset)?;
+                self.write_u32(section, offset, (orig & 0xFC00_0003) | (s & 0x03FF_FFFC))
+            }
+            RelocationType::Addr14 => {
+                let orig = self.read_u32(section, offset)?;
+                self.write_u32(section, offset, (orig & 0xFFFF_0003) | (s & 0xFFFC))
+            }
+            RelocationType::Rel24 => {
+                let orig = self.read_u32(section, offset)?;
+                let value = s.wrapping_sub(site_address) & 0x03FF_FFFC;
+                self.write_u32(section, offset, (orig & 0xFC00_0003) | value)
+            }
+            RelocationType::Rel14 => {
+                let orig = self.read_u32(section, offset)?;
+                let value = s.wrapping_sub(site_address) & 0xFFFC;
+                self.write_u32(section, offset, (orig & 0xFFFF_0003) | value)
+            }
+            RelocationType::Other(other) => Err(RelError::UnsupportedRelocation(other)),
+        }
+    }
+
+    /// Applies all relocations against this module and the DOL, like when the module is
+    /// linked at runtime. `section_addresses` contains the load address of every section
+    pub fn apply_relocations(&mut self, section_addresses: &[u32]) -> Result<(), RelError> {
+        let address_of = |section: u8| {
+            section_addresses
+                .get(section as usize)
+                .copied()
+                .ok_or(RelError::InvalidSection(section))
+        };
+        let own_id = self.header.id;
+        let imports = self.imports.clone();
+        for import in imports
+            .iter()
+            .filter(|import| import.module_id == 0 || import.module_id == own_id)
+        {
+            for relocation in &import.relocations {
+                let symbol_address = if import.module_id == 0 {
+                    relocation.addend
+                } else {
+                    address_of(relocation.target_section)?.wrapping_add(relocation.addend)
+                };
+                let site_address = address_of(relocation.section)?.wrapping_add(relocation.offset);
+                self.apply_relocation(relocation, symbol_address, site_address)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the module with all patches applied
+    pub fn to_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Rel, RelocationType};
+
+    fn test_rel() -> Vec<u8> {
+        let mut data = vec![0u8; 0x100];
+        let words: [(usize, u32); 12] = [
+            (0x00, 5),    // id
+            (0x0C, 2),    // num sections
+            (0x10, 0x4C), // section info
+            (0x1C, 3),    // version
+            (0x24, 0x80), // relocations
+            (0x28, 0x60), // imports
+            (0x2C, 8),    // import size
+            // section 1: executable, data at 0x70 with size 8
+            (0x54, 0x71),
+            (0x58, 0x8),
+            // import: self, relocations at 0x80
+            (0x60, 5),
+            (0x64, 0x80),
+            (0x70, 0x48000001),
+        ];
+        for (offset, value) in words {
+            data[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+        }
+        // R_DOLPHIN_SECTION 1, ADDR32 at 4 to section 1 + 0x10, R_DOLPHIN_NOP, END
+        let relocs: [[u8; 8]; 4] = [
+            [0, 0, 202, 1, 0, 0, 0, 0],
+            [0, 4, 1, 1, 0, 0, 0, 0x10],
+            [0, 0, 201, 0, 0, 0, 0, 0],
+            [0, 0, 203, 0, 0, 0, 0, 0],
+        ];
+        for (i, reloc) in relocs.iter().enumerate() {
+            data[0x80 + i * 8..][..8].copy_from_slice(reloc);
+        }
+        data
+    }
+
+    #[test]
+    pub fn test_rel_relocations() {
+        let mut rel = Rel::read(test_rel()).unwrap();
+        assert_eq!(rel.get_sections().len(), 2);
+        assert!(rel.get_sections()[1].is_executable());
+        let relocations = &rel.get_imports()[0].relocations;
+        assert_eq!(relocations.len(), 1);
+        assert_eq!(relocations[0].section, 1);
+        assert_eq!(relocations[0].offset, 4);
+        assert_eq!(relocations[0].reloc_type, RelocationType::Addr32);
+        assert_eq!(rel.relocations_in_range(1, 0, 4).count(), 0);
+        assert_eq!(rel.relocations_in_range(1, 6, 4).count(), 1);
+        assert_eq!(rel.relocations_in_range(1, 6, u32::MAX).count(), 1);
+
+        rel.apply_relocations(&[0, 0x80500000]).unwrap();
+        assert_eq!(rel.read_u32(1, 4).unwrap(), 0x80500010);
+        rel.write_u32(1, 0, 0x60000000).unwrap();
+        assert_eq!(rel.read_u32(1, 0).unwrap(), 0x60000000);
+        assert!(rel.write_u32(1, 6, 0).is_err());
+        assert!(rel.read_u32(0, 0).is_err());
+        assert_eq!(
+            &rel.to_bytes()[0x70..0x78],
+            &[0x60, 0, 0, 0, 0x80, 0x50, 0, 0x10]
+        );
+    }
+
+    #[test]
+    pub fn test_rel_corrupt() {
+        let mut data = test_rel();
+        // huge section count
+        data[0x0C..0x10].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(Rel::read(data).is_err());
+
+        let mut data = test_rel();
+        // relocations at the end, with NOPs that move the offset past the end of a u32
+        let relocations_offset = data.len() as u32;
+        data[0x64..0x68].copy_from_slice(&relocations_offset.to_be_bytes());
+        data.extend_from_slice(&[0, 0, 202, 1, 0, 0, 0, 0]);
+        for _ in 0..0x10002 {
+            data.extend_from_slice(&[0xFF, 0xFF, 201, 0, 0, 0, 0, 0]);
+        }
+        data.extend_from_slice(&[0, 4, 1, 1, 0, 0, 0, 0]);
+        data.extend_from_slice(&[0, 0, 203, 0, 0, 0, 0, 0]);
+        let rel = Rel::read(data).unwrap();
+        assert_eq!(rel.get_imports()[0].relocations[0].offset, u32::MAX);
+    }
+}
@@ -0,0 +1,645 @@
+use std::{
+    convert::Infallible,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek},
+    path::{Path, PathBuf},
+};
+
+use roxmltree::Node;
+use thiserror::Error;
+
+use crate::{
+    builder::{InvalidFilePath, IsoPartitionBuilder, PartitionAddError, WiiDiscBuilder},
+    dol::{Dol, DolError},
+    structs::WiiPartType,
+    WiiIsoReader,
+};
+
+#[derive(Error, Debug)]
+pub enum RiivolutionError {
+    #[error("io error: {0}")]
+    IO(#[from] io::Error),
+    #[error("binrw error: {0}")]
+    BinRW(#[from] binrw::Error),
+    #[error("xml error: {0}")]
+    Xml(#[from] roxmltree::Error),
+    #[error("dol error: {0}")]
+    Dol(#[from] DolError),
+    #[error("build error: {0}")]
+    Build(#[from] PartitionAddError<Infallible>),
+    #[error("<{element}> is missing the {attribute} attribute")]
+    MissingAttribute {
+        element: &'static str,
+        attribute: &'static str,
+    },
+    #[error("invalid value for {attribute}: {value}")]
+    InvalidValue {
+        attribute: &'static str,
+        value: String,
+    },
+    #[error("patch {0} doesn't exist")]
+    PatchNotFound(String),
+    #[error("{0}")]
+    InvalidDiscPath(#[from] InvalidFilePath),
+    #[error("patches are for {expected}, but the disc is {actual}")]
+    WrongGame { expected: String, actual: String },
+    #[error("the disc has no data partition")]
+    NoDataPartition,
+    #[error("the {attribute} attribute of <{element}> is not supported")]
+    Unsupported {
+        element: &'static str,
+        attribute: &'static str,
+    },
+}
+
+/// Replaces or patches a single file on the disc
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilePatch {
+    pub disc: String,
+    pub external: String,
+    /// add the file if it doesn't exist on the disc
+    pub create: bool,
+    /// the file size may change
+    pub resize: bool,
+    /// offset into the disc file the external file is written to
+    pub offset: u32,
+    /// only use this many bytes of the external file
+    pub length: Option<u32>,
+}
+
+/// Replaces files of a disc folder with the files of an external folder
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FolderPatch {
+    /// without a disc path, files are replaced wherever a file with the same name is
+    pub disc: Option<String>,
+    pub external: String,
+    pub create: bool,
+    pub recursive: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MemoryValue {
+    Bytes(Vec<u8>),
+    /// path to a file with the data
+    File(String),
+}
+
+/// Patches memory at a fixed address, only addresses in the DOL are supported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryPatch {
+    pub offset: u32,
+    pub value: MemoryValue,
+    /// the patch is only applied if the memory contains these bytes
+    pub original: Option<Vec<u8>>,
+    /// set attributes that aren't supported, like search, ocarina and align.
+    /// Applying the patch fails if there are any
+    pub unsupported: Vec<&'static str>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RiivolutionPatch {
+    pub id: String,
+    /// folder on the SD card external paths are relative to
+    pub root: String,
+    pub files: Vec<FilePatch>,
+    pub folders: Vec<FolderPatch>,
+    pub memory: Vec<MemoryPatch>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RiivolutionChoice {
+    pub name: String,
+    pub patch_ids: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RiivolutionOption {
+    pub section: String,
+    pub name: String,
+    pub choices: Vec<RiivolutionChoice>,
+    /// 1 based index of the selected choice, 0 means disabled
+    pub default: usize,
+}
+
+/// Parsed Riivolution XML
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RiivolutionXml {
+    /// prefix of the game ID the patches are for
+    pub game_id: Option<String>,
+    pub options: Vec<RiivolutionOption>,
+    pub patches: Vec<RiivolutionPatch>,
+}
+
+fn required_attr<'a>(
+    node: &Node<'a, '_>,
+    element: &'static str,
+    attribute: &'static str,
+) -> Result<&'a str, RiivolutionError> {
+    node.attribute(attribute)
+        .ok_or(RiivolutionError::MissingAttribute { element, attribute })
+}
+
+fn parse_bool(node: &Node, attribute: &'static str, default: bool) -> bool {
+    match node.attribute(attribute) {
+        Some(value) => matches!(value.to_ascii_lowercase().as_str(), "true" | "1" | "yes"),
+        None => default,
+    }
+}
+
+fn parse_number(value: &str, attribute: &'static str) -> Result<u32, RiivolutionError> {
+    let parsed = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| RiivolutionError::InvalidValue {
+        attribute,
+        value: value.to_string(),
+    })
+}
+
+fn parse_hex_bytes(value: &str, attribute: &'static str) -> Result<Vec<u8>, RiivolutionError> {
+    let invalid = || RiivolutionError::InvalidValue {
+        attribute,
+        value: value.to_string(),
+    };
+    let hex = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value);
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid()))
+        .collect()
+}
+
+fn parse_patch(node: Node) -> Result<RiivolutionPatch, RiivolutionError> {
+    let mut patch = RiivolutionPatch {
+        id: required_attr(&node, "patch", "id")?.to_string(),
+        root: node.attribute("root").unwrap_or("").to_string(),
+        ..Default::default()
+    };
+    for child in node.children().filter(Node::is_element) {
+        match child.tag_name().name() {
+            "file" => patch.files.push(FilePatch {
+                disc: required_attr(&child, "file", "disc")?.to_string(),
+                external: required_attr(&child, "file", "external")?.to_string(),
+                create: parse_bool(&child, "create", false),
+                resize: parse_bool(&child, "resize", true),
+                offset: child
+                    .attribute("offset")
+                    .map_or(Ok(0), |v| parse_number(v, "offset"))?,
+                length: child
+                    .attribute("length")
+                    .map(|v| parse_number(v, "length"))
+                    .transpose()?,
+            }),
+            "folder" => patch.folders.push(FolderPatch {
+                disc: child.attribute("disc").map(str::to_string),
+                external: required_attr(&child, "folder", "external")?.to_string(),
+                create: parse_bool(&child, "create", false),
+                recursive: parse_bool(&child, "recursive", true),
+            }),
+            "memory" => {
+                let value = match (child.attribute("value"), child.attribute("valuefile")) {
+                    (Some(value), _) => MemoryValue::Bytes(parse_hex_bytes(value, "value")?),
+                    (None, Some(file)) => MemoryValue::File(file.to_string()),
+                    (None, None) => {
+                        return Err(RiivolutionError::MissingAttribute {
+                            element: "memory",
+                            attribute: "value",
+                        })
+                    }
+                };
+                patch.memory.push(MemoryPatch {
+                    offset: parse_number(required_attr(&child, "memory", "offset")?, "offset")?,
+                    value,
+                    original: child
+                        .attribute("original")
+                        .map(|v| parse_hex_bytes(v, "original"))
+                        .transpose()?,
+                    unsupported: ["search", "ocarina", "align"]
+                        .into_iter()
+                        .filter(|attribute| match *attribute {
+                            "align" => child.has_attribute(*attribute),
+                            _ => parse_bool(&child, attribute, false),
+                        })
+                        .collect(),
+                });
+            }
+            _ => (),
+        }
+    }
+    Ok(patch)
+}
+
+fn parse_options(node: Node, options: &mut Vec<RiivolutionOption>) -> Result<(), RiivolutionError> {
+    for section in node.children().filter(|n| n.has_tag_name("section")) {
+        let section_name = section.attribute("name").unwrap_or("");
+        for option in section.children().filter(|n| n.has_tag_name("option")) {
+            let choices = option
+                .children()
+                .filter(|n| n.has_tag_name("choice"))
+                .map(|choice| RiivolutionChoice {
+                    name: choice.attribute("name").unwrap_or("").to_string(),
+                    patch_ids: choice
+                        .children()
+                        .filter(|n| n.has_tag_name("patch"))
+                        .filter_map(|n| n.attribute("id"))
+                        .map(str::to_string)
+                        .collect(),
+                })
+                .collect();
+            options.push(RiivolutionOption {
+                section: section_name.to_string(),
+                name: required_attr(&option, "option", "name")?.to_string(),
+                choices,
+                default: option
+                    .attribute("default")
+                    .map_or(Ok(0), |v| parse_number(v, "default"))?
+                    as usize,
+            });
+        }
+    }
+    Ok(())
+}
+
+impl RiivolutionXml {
+    pub fn parse(xml: &str) -> Result<Self, RiivolutionError> {
+        let doc = roxmltree::Document::parse(xml)?;
+        let mut result = Self::default();
+        for node in doc.root_element().children().filter(Node::is_element) {
+            match node.tag_name().name() {
+                "id" => result.game_id = node.attribute("game").map(str::to_string),
+                "options" => parse_options(node, &mut result.options)?,
+                "patch" => result.patches.push(parse_patch(node)?),
+                _ => (),
+            }
+        }
+        Ok(result)
+    }
+
+    pub fn get_patch(&self, id: &str) -> Option<&RiivolutionPatch> {
+        self.patches.iter().find(|patch| patch.id == id)
+    }
+
+    /// IDs of the patches that are enabled by the default choices of all options
+    pub fn get_default_patch_ids(&self) -> Vec<&str> {
+        self.options
+            .iter()
+            .filter_map(|option| {
+                option
+                    .default
+                    .checked_sub(1)
+                    .and_then(|idx| option.choices.get(idx))
+            })
+            .flat_map(|choice| choice.patch_ids.iter().map(String::as_str))
+            .collect()
+    }
+}
+
+/// external paths starting with / are relative to the SD root, otherwise to the patch root
+fn resolve_external(sd_root: &Path, root: &str, external: &str) -> PathBuf {
+    match external.strip_prefix('/') {
+        Some(external) => sd_root.join(external),
+        None => sd_root.join(root.trim_start_matches('/')).join(external),
+    }
+}
+
+/// collects all files in the directory with the path relative to it
+fn collect_external_files(
+    dir: &Path,
+    prefix: &str,
+    recursive: bool,
+    out: &mut Vec<(String, PathBuf)>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let relative = if prefix.is_empty() {
+            name
+        } else {
+            format!("{prefix}/{name}")
+        };
+        if entry.file_type()?.is_dir() {
+            if recursive {
+                collect_external_files(&entry.path(), &relative, recursive, out)?;
+            }
+        } else {
+            out.push((relative, entry.path()));
+        }
+    }
+    Ok(())
+}
+
+fn apply_file_patch<RS: Read + Seek>(
+    partition: &mut IsoPartitionBuilder<RS>,
+    file: &FilePatch,
+    external: &Path,
+) -> Result<(), RiivolutionError> {
+    let mut data = fs::read(external)?;
+    if let Some(length) = file.length {
+        data.truncate(length as usize);
+    }
+    let offset = file.offset as usize;
+    let mut disc_data = match partition.read_file(&file.disc) {
+        Some(original) => {
+            if offset == 0 && file.resize {
+                return Ok(partition.set_file(&file.disc, data)?);
+            }
+            original?
+        }
+        None if file.create => Vec::new(),
+        // files that don't exist are skipped
+        None => return Ok(()),
+    };
+    let end = offset + data.len();
+    if end > disc_data.len() {
+        if file.resize || disc_data.is_empty() {
+            disc_data.resize(end, 0);
+        } else {
+            data.truncate(disc_data.len().saturating_sub(offset));
+        }
+    }
+    disc_data[offset..offset + data.len()].copy_from_slice(&data);
+    Ok(partition.set_file(&file.disc, disc_data)?)
+}
+
+fn apply_folder_patch<RS: Read + Seek>(
+    partition: &mut IsoPartitionBuilder<RS>,
+    folder: &FolderPatch,
+    external: &Path,
+) -> Result<(), RiivolutionError> {
+    let mut external_files = Vec::new();
+    collect_external_files(external, "", folder.recursive, &mut external_files)?;
+    for (relative_path, external_path) in external_files {
+        match &folder.disc {
+            Some(disc) => {
+                let disc_path = format!("{}/{}", disc.trim_end_matches('/'), relative_path);
+                if folder.create
+                    || partition
+                        .get_current_fst()
+                        .find_node_path(&disc_path)
+                        .is_some()
+                {
+                    partition.set_file(&disc_path, fs::read(&external_path)?)?;
+                }
+            }
+            None => {
+                // replace every file with this name
+                let matching_paths: Vec<String> = partition
+                    .get_current_fst()
+                    .files()
+                    .filter(|entry| entry.node.get_name() == &relative_path)
+                    .map(|entry| entry.path)
+                    .collect();
+                if !matching_paths.is_empty() {
+                    let data = fs::read(&external_path)?;
+                    for disc_path in matching_paths {
+                        partition.set_file(&disc_path, data.clone())?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Applies memory patches to the DOL, patches with a mismatching original value are skipped.
+/// Patches with an address outside of the DOL are skipped too and returned
+pub fn apply_memory_patches<'a>(
+    dol: &mut Dol,
+    patches: &'a [MemoryPatch],
+    sd_root: &Path,
+    root: &str,
+) -> Result<Vec<&'a MemoryPatch>, RiivolutionError> {
+    if let Some(attribute) = patches.iter().find_map(|patch| patch.unsupported.first()) {
+        return Err(RiivolutionError::Unsupported {
+            element: "memory",
+            attribute,
+        });
+    }
+    let mut unmapped = Vec::new();
+    for patch in patches {
+        let value = match &patch.value {
+            MemoryValue::Bytes(value) => value.clone(),
+            MemoryValue::File(file) => fs::read(resolve_external(sd_root, root, file))?,
+        };
+        let original_len = patch.original.as_ref().map_or(0, Vec::len);
+        if dol
+            .find_section(patch.offset, value.len().max(original_len))
+            .is_none()
+        {
+            unmapped.push(patch);
+            continue;
+        }
+        if let Some(original) = &patch.original {
+            if dol.read_bytes(patch.offset, original.len())? != original.as_slice() {
+                continue;
+            }
+        }
+        dol.write_bytes(patch.offset, &value)?;
+    }
+    Ok(unmapped)
+}
+
+/// Applies all file, folder and memory patches of a patch to the partition,
+/// `sd_root` is the folder that corresponds to the root of the SD card.
+/// Returns the memory patches that were skipped because they are outside of the DOL
+pub fn apply_patch<'a, RS: Read + Seek>(
+    partition: &mut IsoPartitionBuilder<RS>,
+    patch: &'a RiivolutionPatch,
+    sd_root: &Path,
+) -> Result<Vec<&'a MemoryPatch>, RiivolutionError> {
+    for file in &patch.files {
+        apply_file_patch(
+            partition,
+            file,
+            &resolve_external(sd_root, &patch.root, &file.external),
+        )?;
+    }
+    for folder in &patch.folders {
+        apply_folder_patch(
+            partition,
+            folder,
+            &resolve_external(sd_root, &patch.root, &folder.external),
+        )?;
+    }
+    if patch.memory.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut dol = Dol::read(&partition.read_dol()?)?;
+    let unmapped = apply_memory_patches(&mut dol, &patch.memory, sd_root, &patch.root)?;
+    partition.set_dol(dol.to_bytes()?);
+    Ok(unmapped)
+}
+
+/// Builds a new ISO from `src` with the given patches applied to the data partition.
+/// Returns the memory patches that were skipped because they are outside of the DOL
+pub fn build_patched_iso<'a, C: FnMut(u8)>(
+    src: &Path,
+    dest: &Path,
+    xml: &'a RiivolutionXml,
+    patch_ids: &[&str],
+    sd_root: &Path,
+    progress_cb: &mut C,
+) -> Result<Vec<&'a MemoryPatch>, RiivolutionError> {
+    let mut reader = WiiIsoReader::open(File::open(src)?)?;
+    if let Some(game_id) = &xml.game_id {
+        let actual = String::from_utf8_lossy(&reader.get_header().game_id).into_owned();
+        if !actual.starts_with(game_id.as_str()) {
+            return Err(RiivolutionError::WrongGame {
+                expected: game_id.clone(),
+                actual,
+            });
+        }
+    }
+    let patches = patch_ids
+        .iter()
+        .map(|id| {
+            xml.get_patch(id)
+                .ok_or_else(|| RiivolutionError::PatchNotFound(id.to_string()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut builder = WiiDiscBuilder::create(
+        OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .read(true)
+            .write(true)
+            .open(dest)?,
+        reader.get_header().clone(),
+        reader.get_region().clone(),
+    );
+    let data_part = reader
+        .partitions()
+        .iter()
+        .find(|p| p.get_type() == WiiPartType::Data)
+        .ok_or(RiivolutionError::NoDataPartition)?
+        .clone();
+    let mut part_read_info = reader.open_partition(data_part)?;
+    let ticket = part_read_info.get_partition_header().ticket.clone();
+    let tmd = part_read_info.read_tmd(&mut reader)?;
+    let cert_chain = part_read_info.read_certificates(&mut reader)?;
+    let mut partition = IsoPartitionBuilder::new(&mut reader, part_read_info)?;
+    let mut unmapped = Vec::new();
+    for patch in patches {
+        unmapped.extend(apply_patch(&mut partition, patch, sd_root)?);
+    }
+    builder.add_partition(
+        WiiPartType::Data,
+        ticket,
+        tmd,
+        cert_chain,
+        &mut partition,
+        progress_cb,
+    )?;
+    builder.finish()?;
+    Ok(unmapped)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::dol::{Dol, DolSectionKind};
+
+    use super::{apply_memory_patches, MemoryValue, RiivolutionError, RiivolutionXml};
+
+    const TEST_XML: &str = r#"<wiidisc version="1">
+        <id game="SOU"><region type="E"/></id>
+        <options>
+            <section name="Mod">
+                <option name="Enable" default="1">
+                    <choice name="On"><patch id="mod"/></choice>
+                </option>
+                <option name="Extra">
+                    <choice name="On"><patch id="extra"/></choice>
+                </option>
+            </section>
+        </options>
+        <patch id="mod" root="/mod">
+            <file disc="/rels.arc" external="rels.arc" create="true"/>
+            <folder disc="/Stage" external="stage"/>
+            <memory offset="0x80004000" value="60000000" original="11223344"/>
+            <memory offset="0x80004004" value="4E800020"/>
+            <memory offset="0x80001800" value="00000000" original="11223344"/>
+        </patch>
+        <patch id="extra"/>
+    </wiidisc>"#;
+
+    #[test]
+    pub fn test_parse_and_memory_patch() {
+        let xml = RiivolutionXml::parse(TEST_XML).unwrap();
+        assert_eq!(xml.game_id.as_deref(), Some("SOU"));
+        assert_eq!(xml.get_default_patch_ids(), vec!["mod"]);
+        let patch = xml.get_patch("mod").unwrap();
+        assert_eq!(patch.root, "/mod");
+        assert!(patch.files[0].create && patch.files[0].resize);
+        assert_eq!(patch.folders[0].disc.as_deref(), Some("/Stage"));
+        assert_eq!(
+            patch.memory[1].value,
+            MemoryValue::Bytes(vec![0x4E, 0x80, 0x00, 0x20])
+        );
+
+        let mut dol = Dol::new(0x80004000);
+        dol.add_section(DolSectionKind::Text, 0x80004000, vec![0; 0x20])
+            .unwrap();
+        let unmapped =
+            apply_memory_patches(&mut dol, &patch.memory, Path::new("."), &patch.root).unwrap();
+        assert_eq!(unmapped, [&patch.memory[2]]);
+        // original doesn't match
+        assert_eq!(dol.read_u32(0x80004000).unwrap(), 0);
+        assert_eq!(dol.read_u32(0x80004004).unwrap(), 0x4E800020);
+    }
+
+    #[test]
+    pub fn test_unsupported_memory_patch() {
+        let xml = RiivolutionXml::parse(
+            r#"<wiidisc version="1">
+            <patch id="search">
+                <memory offset="0x80004000" value="60000000" original="4E800020" search="true" align="4"/>
+            </patch>
+        </wiidisc>"#,
+        )
+        .unwrap();
+        let patch = xml.get_patch("search").unwrap();
+        assert_eq!(patch.memory[0].unsupported, ["search", "align"]);
+        let mut dol = Dol::new(0x80004000);
+        dol.add_section(DolSectionKind::Text, 0x80004000, vec![0; 0x20])
+            .unwrap();
+        assert!(matches!(
+            apply_memory_patches(&mut dol, &patch.memory, Path::new("."), &patch.root),
+            Err(RiivolutionError::Unsupported {
+                attribute: "search",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    pub fn test_default_patch_ids_skip_disabled() {
+        let xml = RiivolutionXml::parse(
+            r#"<wiidisc version="1">
+            <options>
+                <section name="Mod">
+                    <option name="Off">
+                        <choice name="A"><patch id="off-a"/></choice>
+                    </option>
+                    <option name="On" default="2">
+                        <choice name="A"><patch id="on-a"/></choice>
+                        <choice name="B"><patch id="on-b"/></choice>
+                    </option>
+                </section>
+            </options>
+        </wiidisc>"#,
+        )
+        .unwrap();
+        assert_eq!(xml.get_default_patch_ids(), vec!["on-b"]);
+    }
+}
@@ -0,0 +1,258 @@
+use std::io::{Read, Seek};
+
+use binrw::{BinWrite, BinWriterExt};
+use num_bigint::BigUint;
+use sha1::{Digest, Sha1};
+use thiserror::Error;
+
+use crate::{
+    structs::{null_terminated_str, Certificate, Ticket, TMD},
+    WiiIsoReader, WiiPartitionReadInfo,
+};
+
+/// DER encoded DigestInfo for SHA-1, which precedes the hash in a PKCS#1 v1.5 signature
+const SHA1_DIGEST_INFO: [u8; 15] = [
+    0x30, 0x21, 0x30, 0x09, 0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a, 0x05, 0x00, 0x04, 0x14,
+];
+
+/// Outcome of checking the signature of a ticket, TMD or certificate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureStatus {
+    /// The signature is valid for the issuing certificate, which is valid itself
+    Official,
+    /// The signature is zeroed and the SHA-1 of the signed data starts with a 0 byte,
+    /// this passes because IOS compares the hashes with strncmp
+    Fakesigned,
+    /// Neither officially signed nor fakesigned, the data was modified
+    Invalid,
+}
+
+#[derive(Error, Debug)]
+pub enum SignatureError {
+    #[error("issuer {0} is not part of the certificate chain")]
+    IssuerNotFound(String),
+    #[error("binrw error: {0}")]
+    BinRW(#[from] binrw::Error),
+}
+
+/// Signature status of the ticket and TMD of a partition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TitleSignatures {
+    pub ticket: SignatureStatus,
+    pub tmd: SignatureStatus,
+}
+
+impl TitleSignatures {
+    pub fn is_official(&self) -> bool {
+        self.ticket == SignatureStatus::Official && self.tmd == SignatureStatus::Official
+    }
+}
+
+/// Returns the part of the serialized value that is covered by the signature,
+/// everything after the signature type, the signature and its padding
+pub(crate) fn signed_data<T>(value: &T, sig_len: usize) -> binrw::BinResult<Vec<u8>>
+where
+    T: for<'a> BinWrite<Args<'a> = ()>,
+{
+    let mut buf = Vec::new();
+    std::io::Cursor::new(&mut buf).write_be(value)?;
+    buf.drain(..4 + sig_len + 0x3C);
+    Ok(buf)
+}
+
+/// Builds the PKCS#1 v1.5 encoded message for a SHA-1 hash and a key of the given length
+fn pkcs1_sha1_encode(key_len: usize, hash: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![0xFF; key_len];
+    encoded[0] = 0;
+    encoded[1] = 1;
+    let digest_info_start = key_len - hash.len() - SHA1_DIGEST_INFO.len();
+    encoded[digest_info_start - 1] = 0;
+    encoded[digest_info_start..][..SHA1_DIGEST_INFO.len()].copy_from_slice(&SHA1_DIGEST_INFO);
+    encoded[key_len - hash.len()..].copy_from_slice(hash);
+    encoded
+}
+
+fn rsa_verify(cert: &Certificate, sig: &[u8], hash: &[u8]) -> bool {
+    let key_len = cert.modulus().len();
+    let modulus = BigUint::from_bytes_be(cert.modulus());
+    if sig.len() != key_len || key_len < 64 || modulus == BigUint::ZERO {
+        return false;
+    }
+    let decrypted = BigUint::from_bytes_be(sig)
+        .modpow(&BigUint::from(cert.pub_exp), &modulus)
+        .to_bytes_be();
+    // leading zeros are stripped from the bigint
+    let mut padded = vec![0; key_len.saturating_sub(decrypted.len())];
+    padded.extend_from_slice(&decrypted);
+    padded == pkcs1_sha1_encode(key_len, hash)
+}
+
+fn check_signature(
+    sig: &[u8],
+    issuer: &str,
+    data: &[u8],
+    certs: &[Certificate],
+    depth: usize,
+) -> Result<SignatureStatus, SignatureError> {
+    let hash = Sha1::digest(data);
+    if sig.iter().all(|b| *b == 0) {
+        return Ok(if hash[0] == 0 {
+            SignatureStatus::Fakesigned
+        } else {
+            SignatureStatus::Invalid
+        });
+    }
+    let cert = certs
+        .iter()
+        .find(|c| c.full_name() == issuer)
+        .ok_or_else(|| SignatureError::IssuerNotFound(issuer.to_string()))?;
+    // a chain can't be longer than the amount of certificates, so this is a cycle
+    if depth > certs.len() || !rsa_verify(cert, sig, &hash) {
+        return Ok(SignatureStatus::Invalid);
+    }
+    // a valid signature only counts if the issuer is valid as well
+    match verify_certificate_rec(cert, certs, depth + 1)? {
+        SignatureStatus::Official => Ok(SignatureStatus::Official),
+        _ => Ok(SignatureStatus::Invalid),
+    }
+}
+
+fn verify_certificate_rec(
+    cert: &Certificate,
+    certs: &[Certificate],
+    depth: usize,
+) -> Result<SignatureStatus, SignatureError> {
+    let issuer = null_terminated_str(&cert.issuer);
+    // the root key is not stored on discs, so the CA certificate is the trust anchor
+    if issuer == "Root" {
+        return Ok(SignatureStatus::Official);
+    }
+    let data = signed_data(cert, cert.sig.len())?;
+    check_signature(&cert.sig, &issuer, &data, certs, depth)
+}
+
+/// Verifies a certificate against the others in the chain,
+/// certificates issued by the root are trusted as is
+pub fn verify_certificate(
+    cert: &Certificate,
+    certs: &[Certificate],
+) -> Result<SignatureStatus, SignatureError> {
+    verify_certificate_rec(cert, certs, 0)
+}
+
+/// Verifies the signature of a ticket, walking the certificate chain up to the CA
+pub fn verify_ticket(
+    ticket: &Ticket,
+    certs: &[Certificate],
+) -> Result<SignatureStatus, SignatureError> {
+    let data = signed_data(ticket, ticket.sig.len())?;
+    check_signature(
+        &ticket.sig,
+        &null_terminated_str(&ticket.sig_issuer),
+        &data,
+        certs,
+        0,
+    )
+}
+
+/// Verifies the signature of a TMD, walking the certificate chain up to the CA
+pub fn verify_tmd(tmd: &TMD, certs: &[Certificate]) -> Result<SignatureStatus, SignatureError> {
+    let data = signed_data(tmd, tmd.sig.len())?;
+    check_signature(
+        &tmd.sig,
+        &null_terminated_str(&tmd.sig_issuer),
+        &data,
+        certs,
+        0,
+    )
+}
+
+impl WiiPartitionReadInfo {
+    /// Checks the ticket and TMD of this partition against its certificate chain
+    pub fn verify_signatures<RS: Read + Seek>(
+        &mut self,
+        reader: &mut WiiIsoReader<RS>,
+    ) -> Result<TitleSignatures, SignatureError> {
+        let certs = self.read_certificates(reader)?;
+        let tmd = self.read_tmd(reader)?;
+        Ok(TitleSignatures {
+            ticket: verify_ticket(&self.get_partition_header().ticket, &certs)?,
+            tmd: verify_tmd(&tmd, &certs)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use binrw::BinReaderExt;
+    use sha1::{Digest, Sha1};
+
+    use crate::structs::{Certificate, KeyType, SigType, Ticket};
+
+    use super::{pkcs1_sha1_encode, signed_data, verify_ticket, SignatureStatus};
+
+    fn name(s: &str) -> [u8; 64] {
+        let mut buf = [0; 64];
+        buf[..s.len()].copy_from_slice(s.as_bytes());
+        buf
+    }
+
+    // with an exponent of 1 the signature is just the encoded message,
+    // which makes it possible to test the chain without real keys
+    fn identity_cert(issuer: &str, subject: &str) -> Certificate {
+        Certificate {
+            sig_type: SigType::Rsa2048,
+            sig: vec![0; 256],
+            issuer: name(issuer),
+            key_type: KeyType::Rsa2048,
+            subject: name(subject),
+            key_id: 0,
+            key: vec![0xFF; 256],
+            pub_exp: 1,
+        }
+    }
+
+    fn sign_with_identity(data: &[u8]) -> Vec<u8> {
+        pkcs1_sha1_encode(256, &Sha1::digest(data))
+    }
+
+    fn test_ticket() -> Ticket {
+        let mut buf = vec![0u8; 0x2A4];
+        buf[..4].copy_from_slice(&0x00010001u32.to_be_bytes());
+        buf[0x140..][..26].copy_from_slice(b"Root-CA00000001-XS00000003");
+        Cursor::new(&buf).read_be().unwrap()
+    }
+
+    #[test]
+    pub fn test_verify_chain() {
+        let ca = identity_cert("Root", "CA00000001");
+        let mut xs = identity_cert("Root-CA00000001", "XS00000003");
+        xs.sig = sign_with_identity(&signed_data(&xs, 256).unwrap());
+        let certs = [ca, xs];
+
+        let mut ticket = test_ticket();
+        let sig = sign_with_identity(&signed_data(&ticket, 256).unwrap());
+        ticket.sig.copy_from_slice(&sig);
+        assert_eq!(
+            verify_ticket(&ticket, &certs).unwrap(),
+            SignatureStatus::Official
+        );
+
+        ticket.title_id[7] ^= 1;
+        assert_eq!(
+            verify_ticket(&ticket, &certs).unwrap(),
+            SignatureStatus::Invalid
+        );
+
+        // breaking the intermediate certificate invalidates everything it signed
+        let mut certs = certs;
+        ticket.title_id[7] ^= 1;
+        certs[1].key_id = 1;
+        assert_eq!(
+            verify_ticket(&ticket, &certs).unwrap(),
+            SignatureStatus::Invalid
+        );
+    }
+}
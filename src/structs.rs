@@ -1,4 +1,7 @@
-use std::io::{Read, Seek, SeekFrom};
+use std::{
+    borrow::Cow,
+    io::{Read, Seek, SeekFrom},
+};
 
 use aes::{
     cipher::{block_padding::NoPadding, BlockDecryptMut},
@@ -6,6 +9,7 @@ use aes::{
     Aes128,
 };
 use binrw::{binrw, BinReaderExt, NullString};
+use thiserror::Error;
 
 use crate::{ShiftedU64, COMMON_KEYS};
 
@@ -98,24 +102,42 @@ pub struct TicketTimeLimit {
     pub time_limit: u32,
 }
 
-fn decrypt_title_key(key: &[u8; 16], common_key_idx: u8, title_id: &[u8; 8]) -> [u8; 16] {
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("invalid common key index {0}, only {count} common keys are known", count = COMMON_KEYS.len())]
+pub struct InvalidCommonKeyIdx(pub u8);
+
+fn get_common_key(common_key_idx: u8) -> Result<&'static [u8; 16], InvalidCommonKeyIdx> {
+    COMMON_KEYS
+        .get(common_key_idx as usize)
+        .ok_or(InvalidCommonKeyIdx(common_key_idx))
+}
+
+fn decrypt_title_key(
+    key: &[u8; 16],
+    common_key_idx: u8,
+    title_id: &[u8; 8],
+) -> Result<[u8; 16], InvalidCommonKeyIdx> {
     let mut decrypted = [0; 16];
     let mut iv = [0u8; 0x10];
     iv[..8].copy_from_slice(title_id);
-    Aes128CbcDec::new(&COMMON_KEYS[common_key_idx as usize].into(), &iv.into())
+    Aes128CbcDec::new(get_common_key(common_key_idx)?.into(), &iv.into())
         .decrypt_padded_b2b_mut::<NoPadding>(key, &mut decrypted)
         .unwrap();
-    decrypted
+    Ok(decrypted)
 }
 
-fn encrypt_title_key(key: &[u8; 16], common_key_idx: u8, title_id: &[u8; 8]) -> [u8; 16] {
+fn encrypt_title_key(
+    key: &[u8; 16],
+    common_key_idx: u8,
+    title_id: &[u8; 8],
+) -> Result<[u8; 16], InvalidCommonKeyIdx> {
     let mut encrypted = [0; 16];
     let mut iv = [0u8; 0x10];
     iv[..8].copy_from_slice(title_id);
-    Aes128CbcEnc::new(&COMMON_KEYS[common_key_idx as usize].into(), &iv.into())
+    Aes128CbcEnc::new(get_common_key(common_key_idx)?.into(), &iv.into())
         .encrypt_padded_b2b_mut::<NoPadding>(key, &mut encrypted)
         .unwrap();
-    encrypted
+    Ok(encrypted)
 }
 
 #[binrw]
@@ -128,7 +150,7 @@ pub struct Ticket {
     pub ecdh: [u8; 0x3C],
     #[brw(pad_before = 3)]
     #[br(temp)]
-    #[bw(calc = encrypt_title_key(title_key, *common_key_idx, title_id))]
+    #[bw(try_calc = encrypt_title_key(title_key, *common_key_idx, title_id))]
     encrypted_key: [u8; 16],
     #[brw(pad_before = 1)]
     pub ticket_id: [u8; 8],
@@ -140,12 +162,13 @@ pub struct Ticket {
     pub permit_mask: u32,
     pub title_export_allowed: u8,
     pub common_key_idx: u8,
-    #[brw(pad_before = 48)]
+    /// unused area at 0x1F2, fakesigning changes its first 8 bytes
+    pub fakesign_padding: [u8; 0x30],
     pub content_access_permissions: [u8; 0x40],
     pub unk2: u16,
     pub time_limits: [TicketTimeLimit; 8],
     #[bw(ignore)]
-    #[br(calc = decrypt_title_key(&encrypted_key, common_key_idx, &title_id))]
+    #[br(try_calc = decrypt_title_key(&encrypted_key, common_key_idx, &title_id))]
     pub title_key: [u8; 16],
 }
 
@@ -176,7 +199,7 @@ pub struct TMD {
     pub title_id_minor: [u8; 4],
     pub title_type: u32,
     pub group_id: u16,
-    // used to cal
+    /// unused area at 0x19A that fakesigning writes to
     pub fakesign_padding: [u64; 7],
     #[brw(pad_before = 6)]
     pub access_flags: u32,
@@ -201,13 +224,36 @@ pub struct Certificate {
     pub issuer: [u8; 0x40],
     pub key_type: KeyType,
     pub subject: [u8; 64],
+    pub key_id: u32,
+    /// RSA modulus of the public key
     #[br(count = if key_type == KeyType::Rsa4096 { 512 } else if key_type == KeyType::Rsa2048 { 256 } else { 0 })]
     pub key: Vec<u8>,
-    pub modulus: u32,
     #[brw(pad_after = 52)]
     pub pub_exp: u32,
 }
 
+impl Certificate {
+    /// RSA modulus of the public key, empty for other key types
+    pub fn modulus(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Name other signatures use to reference this certificate as their issuer,
+    /// e.g. `Root-CA00000001-XS00000003`
+    pub fn full_name(&self) -> String {
+        format!(
+            "{}-{}",
+            null_terminated_str(&self.issuer),
+            null_terminated_str(&self.subject)
+        )
+    }
+}
+
+pub(crate) fn null_terminated_str(bytes: &[u8]) -> Cow<'_, str> {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end])
+}
+
 #[binrw]
 #[derive(Clone, Debug, PartialEq)]
 pub struct WiiPartitionHeader {
@@ -262,6 +308,39 @@ pub struct DiscHeader {
     pub user_sz: u32,
 }
 
+/// How the data of the partitions is stored on the disc,
+/// set with the flags in the disc header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionDataMode {
+    /// blocks with hashes, encrypted with the title key, used for all retail discs
+    Encrypted,
+    /// blocks with hashes, but the data isn't encrypted
+    Unencrypted,
+    /// neither hashes nor encryption, the data is stored contiguously
+    Plain,
+}
+
+impl DiscHeader {
+    pub fn get_partition_data_mode(&self) -> PartitionDataMode {
+        // without hashes, data is never encrypted
+        if self.disable_hash_verification != 0 {
+            PartitionDataMode::Plain
+        } else if self.disable_disc_enc != 0 {
+            PartitionDataMode::Unencrypted
+        } else {
+            PartitionDataMode::Encrypted
+        }
+    }
+
+    pub fn set_partition_data_mode(&mut self, mode: PartitionDataMode) {
+        (self.disable_hash_verification, self.disable_disc_enc) = match mode {
+            PartitionDataMode::Encrypted => (0, 0),
+            PartitionDataMode::Unencrypted => (0, 1),
+            PartitionDataMode::Plain => (1, 1),
+        };
+    }
+}
+
 #[binrw]
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct DOLHeader {
@@ -283,3 +362,177 @@ pub struct ApploaderHeader {
     pub size1: u32,
     pub size2: u32,
 }
+
+pub const APPLOADER_HEADER_SIZE: u32 = 0x20;
+
+/// Apploader of a partition, it loads the DOL and FST
+#[binrw]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Apploader {
+    /// build date, like "2008/04/25"
+    #[brw(pad_size_to(0x10))]
+    #[br(map = |s| NullString::to_string(&s))]
+    #[bw(map = |s| NullString::from(s.clone()))]
+    pub date: String,
+    pub entry_point: u32,
+    /// size of the apploader code
+    pub size: u32,
+    #[brw(pad_after = 4)]
+    pub trailer_size: u32,
+    /// code and trailer
+    #[br(parse_with = parse_apploader_data, args(size, trailer_size))]
+    pub data: Vec<u8>,
+}
+
+/// reads the code and trailer, the sizes are checked against the remaining input
+/// before anything is allocated
+#[binrw::parser(reader)]
+fn parse_apploader_data(size: u32, trailer_size: u32) -> binrw::BinResult<Vec<u8>> {
+    let pos = reader.stream_position()?;
+    let end = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(pos))?;
+    let length = size
+        .checked_add(trailer_size)
+        .filter(|length| length.checked_add(APPLOADER_HEADER_SIZE).is_some())
+        .filter(|length| *length as u64 <= end - pos)
+        .ok_or_else(|| binrw::Error::AssertFail {
+            pos,
+            message: format!(
+                "apploader size {size:#x} and trailer size {trailer_size:#x} exceed the data"
+            ),
+        })?;
+    let mut data = vec![0; length as usize];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+impl Apploader {
+    /// size of the apploader including the header
+    pub fn full_size(&self) -> u32 {
+        APPLOADER_HEADER_SIZE
+            .saturating_add(self.size)
+            .saturating_add(self.trailer_size)
+    }
+}
+
+pub const REGION_JAPAN: u32 = 0;
+pub const REGION_USA: u32 = 1;
+pub const REGION_EUROPE: u32 = 2;
+/// not a retail region, discs with it boot on consoles of all regions
+pub const REGION_FREE: u32 = 3;
+pub const REGION_KOREA: u32 = 4;
+
+/// age rating that doesn't restrict anything
+pub const AGE_RATING_DISABLED: u8 = 0x80;
+
+/// Region information at 0x4E000 on the disc, checked by the system menu
+#[binrw]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub region_code: u32,
+    /// kept as is, so rebuilding a disc doesn't change it
+    pub unknown: [u8; 12],
+    /// age ratings of the different rating boards, highest bit set means no restriction
+    pub age_ratings: [u8; 16],
+}
+
+impl Region {
+    pub fn region_free() -> Self {
+        Self {
+            region_code: REGION_FREE,
+            unknown: [0; 12],
+            age_ratings: [AGE_RATING_DISABLED; 16],
+        }
+    }
+
+    /// Removes the region and age restrictions, other data is kept
+    pub fn set_region_free(&mut self) {
+        self.region_code = REGION_FREE;
+        self.age_ratings = [AGE_RATING_DISABLED; 16];
+    }
+}
+
+pub const BI2_SIZE: usize = 0x2000;
+
+/// Disc information that is loaded to 0x80003000 in memory
+#[binrw]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bi2 {
+    pub debug_monitor_size: u32,
+    pub simulated_memory_size: u32,
+    pub argument_offset: u32,
+    pub debug_flag: u32,
+    pub track_location: u32,
+    pub track_size: u32,
+    /// one of the `REGION_*` constants
+    pub region_code: u32,
+    pub total_disc: u32,
+    pub long_file_name_support: u32,
+    pub pad_spec: u32,
+    pub dol_limit: u32,
+    pub unknown: u32,
+    /// nonzero for dual layer discs
+    pub dual_layer_value: u32,
+    /// rest of the 0x2000 bytes, unused by retail games
+    #[br(count = BI2_SIZE - 0x34)]
+    #[bw(assert(remaining.len() == BI2_SIZE - 0x34))]
+    pub remaining: Vec<u8>,
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use binrw::{BinReaderExt, BinWriterExt};
+
+    use super::{Apploader, Bi2, Region, BI2_SIZE, REGION_EUROPE};
+
+    #[test]
+    pub fn test_apploader_bi2_region_roundtrip() {
+        let mut data = b"2008/04/25\0\0\0\0\0\0".to_vec();
+        for value in [0x81200000u32, 8, 4, 0] {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+        data.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+        let apploader: Apploader = Cursor::new(&data).read_be().unwrap();
+        assert_eq!(apploader.date, "2008/04/25");
+        assert_eq!(apploader.entry_point, 0x81200000);
+        assert_eq!(apploader.full_size(), 0x2C);
+        // sizes that overflow or exceed the data
+        for (size, trailer_size) in [(u32::MAX, 1u32), (0xFFFFFFF0, 0), (0x10, 0)] {
+            let mut data = data.clone();
+            data[0x14..0x18].copy_from_slice(&size.to_be_bytes());
+            data[0x18..0x1C].copy_from_slice(&trailer_size.to_be_bytes());
+            assert!(Cursor::new(&data).read_be::<Apploader>().is_err());
+        }
+        let mut out = Vec::new();
+        Cursor::new(&mut out).write_be(&apploader).unwrap();
+        assert_eq!(out, data);
+
+        let mut data = vec![0; BI2_SIZE];
+        data[0x1B] = 2;
+        let mut bi2: Bi2 = Cursor::new(&data).read_be().unwrap();
+        assert_eq!(bi2.region_code, 2);
+        bi2.region_code = 1;
+        let mut out = Vec::new();
+        Cursor::new(&mut out).write_be(&bi2).unwrap();
+        data[0x1B] = 1;
+        assert_eq!(out, data);
+
+        let mut data = vec![0; 0x20];
+        data[3] = 2;
+        data[4..0x10].fill(0x55);
+        data[0x10..].fill(0x12);
+        let mut region: Region = Cursor::new(&data).read_be().unwrap();
+        assert_eq!(region.region_code, REGION_EUROPE);
+        let mut out = Vec::new();
+        Cursor::new(&mut out).write_be(&region).unwrap();
+        assert_eq!(out, data);
+        region.set_region_free();
+        let mut out = Vec::new();
+        Cursor::new(&mut out).write_be(&region).unwrap();
+        data[3] = 3;
+        data[0x10..].fill(0x80);
+        assert_eq!(out, data);
+    }
+}
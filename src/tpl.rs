@@ -0,0 +1,690 @@
+use std::io::Cursor;
+
+use binrw::{binrw, BinReaderExt, BinWriterExt};
+use thiserror::Error;
+
+const IMAGE_TABLE_OFFSET: u32 = 0x0C;
+const IMAGE_HEADER_SIZE: u32 = 0x24;
+const PALETTE_HEADER_SIZE: u32 = 0x0C;
+// alignment of image and palette data in the file
+const DATA_ALIGNMENT: usize = 0x20;
+
+#[derive(Error, Debug)]
+pub enum TplError {
+    #[error("binrw error: {0}")]
+    BinRW(#[from] binrw::Error),
+    #[error("texture data at {offset:#x} with size {size:#x} is out of bounds")]
+    DataOutOfBounds { offset: u32, size: usize },
+    #[error("texture data has size {actual:#x}, but {expected:#x} is needed")]
+    InvalidDataSize { expected: usize, actual: usize },
+    #[error("format {0:?} needs a palette")]
+    MissingPalette(TextureFormat),
+    #[error("encoding to {0:?} is not supported")]
+    UnsupportedEncoding(TextureFormat),
+}
+
+#[binrw]
+#[brw(big, repr = u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureFormat {
+    I4 = 0x0,
+    I8 = 0x1,
+    IA4 = 0x2,
+    IA8 = 0x3,
+    RGB565 = 0x4,
+    RGB5A3 = 0x5,
+    RGBA8 = 0x6,
+    C4 = 0x8,
+    C8 = 0x9,
+    C14X2 = 0xA,
+    CMPR = 0xE,
+}
+
+impl TextureFormat {
+    /// width and height of a block in pixels and its size in bytes
+    fn block_layout(self) -> (usize, usize, usize) {
+        match self {
+            TextureFormat::I4 | TextureFormat::C4 | TextureFormat::CMPR => (8, 8, 32),
+            TextureFormat::I8 | TextureFormat::IA4 | TextureFormat::C8 => (8, 4, 32),
+            TextureFormat::IA8
+            | TextureFormat::RGB565
+            | TextureFormat::RGB5A3
+            | TextureFormat::C14X2 => (4, 4, 32),
+            TextureFormat::RGBA8 => (4, 4, 64),
+        }
+    }
+
+    pub fn uses_palette(self) -> bool {
+        matches!(
+            self,
+            TextureFormat::C4 | TextureFormat::C8 | TextureFormat::C14X2
+        )
+    }
+
+    /// size of the encoded data of a texture, incomplete blocks are padded
+    pub fn data_size(self, width: usize, height: usize) -> usize {
+        let (block_width, block_height, block_size) = self.block_layout();
+        width.div_ceil(block_width) * height.div_ceil(block_height) * block_size
+    }
+}
+
+#[binrw]
+#[brw(big, repr = u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaletteFormat {
+    IA8 = 0,
+    RGB565 = 1,
+    RGB5A3 = 2,
+}
+
+#[binrw]
+#[brw(big, magic = 0x0020AF30u32)]
+#[derive(Clone, Debug)]
+struct TplHeader {
+    image_count: u32,
+    image_table_off: u32,
+}
+
+#[binrw]
+#[brw(big)]
+#[derive(Clone, Debug)]
+struct ImageTableEntry {
+    image_header_off: u32,
+    /// 0 if the image doesn't have a palette
+    palette_header_off: u32,
+}
+
+#[binrw]
+#[brw(big)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct TplImageHeader {
+    pub height: u16,
+    pub width: u16,
+    pub format: TextureFormat,
+    /// only valid directly after reading
+    pub data_off: u32,
+    pub wrap_s: u32,
+    pub wrap_t: u32,
+    pub min_filter: u32,
+    pub mag_filter: u32,
+    pub lod_bias: f32,
+    pub edge_lod_enable: u8,
+    pub min_lod: u8,
+    pub max_lod: u8,
+    pub unpacked: u8,
+}
+
+#[binrw]
+#[brw(big)]
+#[derive(Clone, Debug)]
+struct PaletteHeader {
+    entry_count: u16,
+    #[brw(pad_after = 1)]
+    unpacked: u8,
+    format: PaletteFormat,
+    data_off: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TplPalette {
+    pub format: PaletteFormat,
+    pub entries: Vec<u16>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TplImage {
+    pub header: TplImageHeader,
+    /// encoded texture data
+    pub data: Vec<u8>,
+    pub palette: Option<TplPalette>,
+}
+
+impl TplImage {
+    /// Encodes RGBA pixels into a new image with clamping and linear filtering
+    pub fn from_rgba(
+        width: u16,
+        height: u16,
+        format: TextureFormat,
+        rgba: &[u8],
+    ) -> Result<Self, TplError> {
+        let data = encode_texture(rgba, width as usize, height as usize, format)?;
+        Ok(Self {
+            header: TplImageHeader {
+                height,
+                width,
+                format,
+                data_off: 0,
+                wrap_s: 0,
+                wrap_t: 0,
+                min_filter: 1,
+                mag_filter: 1,
+                lod_bias: 0.0,
+                edge_lod_enable: 0,
+                min_lod: 0,
+                max_lod: 0,
+                unpacked: 0,
+            },
+            data,
+            palette: None,
+        })
+    }
+
+    /// Decodes the image to RGBA pixels, row by row
+    pub fn decode_rgba(&self) -> Result<Vec<u8>, TplError> {
+        decode_texture(
+            &self.data,
+            self.header.width as usize,
+            self.header.height as usize,
+            self.header.format,
+            self.palette.as_ref(),
+        )
+    }
+}
+
+/// Texture file with one or more images
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Tpl {
+    pub images: Vec<TplImage>,
+}
+
+fn get_data(data: &[u8], offset: u32, size: usize) -> Result<&[u8], TplError> {
+    data.get(offset as usize..)
+        .and_then(|d| d.get(..size))
+        .ok_or(TplError::DataOutOfBounds { offset, size })
+}
+
+// only works with power of 2
+fn align_next(num: usize, alignment: usize) -> usize {
+    num.wrapping_add(alignment - 1) & !(alignment - 1)
+}
+
+impl Tpl {
+    pub fn read(data: &[u8]) -> Result<Self, TplError> {
+        let mut cursor = Cursor::new(data);
+        let header: TplHeader = cursor.read_be()?;
+        cursor.set_position(header.image_table_off as u64);
+        // the image count comes from the file, so it's not used to preallocate
+        let mut table = Vec::new();
+        for _ in 0..header.image_count {
+            table.push(cursor.read_be::<ImageTableEntry>()?);
+        }
+        let mut images = Vec::with_capacity(table.len());
+        for entry in table {
+            cursor.set_position(entry.image_header_off as u64);
+            let image_header: TplImageHeader = cursor.read_be()?;
+            let size = image_header
+                .format
+                .data_size(image_header.width as usize, image_header.height as usize);
+            let image_data = get_data(data, image_header.data_off, size)?.to_vec();
+            let palette = if entry.palette_header_off != 0 {
+                cursor.set_position(entry.palette_header_off as u64);
+                let palette_header: PaletteHeader = cursor.read_be()?;
+                let entries = get_data(
+                    data,
+                    palette_header.data_off,
+                    palette_header.entry_count as usize * 2,
+                )?
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect();
+                Some(TplPalette {
+                    format: palette_header.format,
+                    entries,
+                })
+            } else {
+                None
+            };
+            images.push(TplImage {
+                header: image_header,
+                data: image_data,
+                palette,
+            });
+        }
+        Ok(Self { images })
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, TplError> {
+        // table and headers first, then all data
+        let mut header_off = IMAGE_TABLE_OFFSET + self.images.len() as u32 * 8;
+        let mut table = Vec::with_capacity(self.images.len());
+        for image in &self.images {
+            let palette_header_off = if image.palette.is_some() {
+                header_off + IMAGE_HEADER_SIZE
+            } else {
+                0
+            };
+            table.push(ImageTableEntry {
+                image_header_off: header_off,
+                palette_header_off,
+            });
+            header_off += IMAGE_HEADER_SIZE;
+            if image.palette.is_some() {
+                header_off += PALETTE_HEADER_SIZE;
+            }
+        }
+        let mut out = vec![0; header_off as usize];
+        let mut headers = Vec::with_capacity(self.images.len());
+        for image in &self.images {
+            let expected = image
+                .header
+                .format
+                .data_size(image.header.width as usize, image.header.height as usize);
+            if image.data.len() != expected {
+                return Err(TplError::InvalidDataSize {
+                    expected,
+                    actual: image.data.len(),
+                });
+            }
+            let palette_header = match &image.palette {
+                Some(palette) => {
+                    out.resize(align_next(out.len(), DATA_ALIGNMENT), 0);
+                    let data_off = out.len() as u32;
+                    for entry in &palette.entries {
+                        out.extend_from_slice(&entry.to_be_bytes());
+                    }
+                    Some(PaletteHeader {
+                        entry_count: palette.entries.len() as u16,
+                        unpacked: 0,
+                        format: palette.format,
+                        data_off,
+                    })
+                }
+                None => None,
+            };
+            out.resize(align_next(out.len(), DATA_ALIGNMENT), 0);
+            let mut image_header = image.header.clone();
+            image_header.data_off = out.len() as u32;
+            out.extend_from_slice(&image.data);
+            headers.push((image_header, palette_header));
+        }
+        out.resize(align_next(out.len(), DATA_ALIGNMENT), 0);
+        let mut cursor = Cursor::new(&mut out);
+        cursor.write_be(&TplHeader {
+            image_count: self.images.len() as u32,
+            image_table_off: IMAGE_TABLE_OFFSET,
+        })?;
+        cursor.write_be(&table)?;
+        for (image_header, palette_header) in headers {
+            cursor.write_be(&image_header)?;
+            if let Some(palette_header) = palette_header {
+                cursor.write_be(&palette_header)?;
+            }
+        }
+        Ok(out)
+    }
+}
+
+// expands a value with the given amount of bits to 8 bits
+fn expand(value: u16, bits: u32) -> u8 {
+    let max = (1u32 << bits) - 1;
+    ((value as u32 & max) * 0xFF / max) as u8
+}
+
+fn rgb565_to_rgba(value: u16) -> [u8; 4] {
+    [
+        expand(value >> 11, 5),
+        expand(value >> 5, 6),
+        expand(value, 5),
+        0xFF,
+    ]
+}
+
+fn rgb5a3_to_rgba(value: u16) -> [u8; 4] {
+    if value & 0x8000 != 0 {
+        [
+            expand(value >> 10, 5),
+            expand(value >> 5, 5),
+            expand(value, 5),
+            0xFF,
+        ]
+    } else {
+        [
+            expand(value >> 8, 4),
+            expand(value >> 4, 4),
+            expand(value, 4),
+            expand(value >> 12, 3),
+        ]
+    }
+}
+
+fn ia8_to_rgba(value: u16) -> [u8; 4] {
+    let [alpha, intensity] = value.to_be_bytes();
+    [intensity, intensity, intensity, alpha]
+}
+
+fn palette_to_rgba(palette: &TplPalette, index: usize) -> [u8; 4] {
+    let value = palette.entries.get(index).copied().unwrap_or(0);
+    match palette.format {
+        PaletteFormat::IA8 => ia8_to_rgba(value),
+        PaletteFormat::RGB565 => rgb565_to_rgba(value),
+        PaletteFormat::RGB5A3 => rgb5a3_to_rgba(value),
+    }
+}
+
+/// decodes a 4x4 DXT1 block
+fn decode_dxt1(block: &[u8]) -> [[u8; 4]; 16] {
+    let c0 = u16::from_be_bytes([block[0], block[1]]);
+    let c1 = u16::from_be_bytes([block[2], block[3]]);
+    let rgba0 = rgb565_to_rgba(c0);
+    let rgba1 = rgb565_to_rgba(c1);
+    let mix = |w0: u16, w1: u16| -> [u8; 4] {
+        let mut color = [0xFF; 4];
+        for i in 0..3 {
+            color[i] = ((rgba0[i] as u16 * w0 + rgba1[i] as u16 * w1) / (w0 + w1)) as u8;
+        }
+        color
+    };
+    let colors = if c0 > c1 {
+        [rgba0, rgba1, mix(2, 1), mix(1, 2)]
+    } else {
+        [rgba0, rgba1, mix(1, 1), [0; 4]]
+    };
+    let mut out = [[0; 4]; 16];
+    for (i, pixel) in out.iter_mut().enumerate() {
+        let indices = block[4 + i / 4];
+        *pixel = colors[(indices >> (6 - (i % 4) * 2)) as usize & 3];
+    }
+    out
+}
+
+/// Decodes a texture to RGBA pixels, row by row. `palette` is needed for C4, C8 and C14X2
+pub fn decode_texture(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    format: TextureFormat,
+    palette: Option<&TplPalette>,
+) -> Result<Vec<u8>, TplError> {
+    let expected = format.data_size(width, height);
+    if data.len() < expected {
+        return Err(TplError::InvalidDataSize {
+            expected,
+            actual: data.len(),
+        });
+    }
+    let palette = match (format.uses_palette(), palette) {
+        (true, None) => return Err(TplError::MissingPalette(format)),
+        (_, palette) => palette,
+    };
+    let (block_width, block_height, block_size) = format.block_layout();
+    let blocks_x = width.div_ceil(block_width);
+    let mut out = vec![0; width * height * 4];
+    for (block_idx, block) in data[..expected].chunks_exact(block_size).enumerate() {
+        let block_x = (block_idx % blocks_x) * block_width;
+        let block_y = (block_idx / blocks_x) * block_height;
+        // CMPR blocks consist of 4 DXT1 blocks
+        let cmpr_pixels = (format == TextureFormat::CMPR).then(|| {
+            [
+                decode_dxt1(&block[0..8]),
+                decode_dxt1(&block[8..16]),
+                decode_dxt1(&block[16..24]),
+                decode_dxt1(&block[24..32]),
+            ]
+        });
+        for i in 0..block_width * block_height {
+            let (x, y) = (block_x + i % block_width, block_y + i / block_width);
+            if x >= width || y >= height {
+                continue;
+            }
+            let u16_at = |idx: usize| u16::from_be_bytes([block[idx * 2], block[idx * 2 + 1]]);
+            let color = match format {
+                TextureFormat::I4 => {
+                    let i4 = (block[i / 2] >> (4 - (i % 2) * 4)) as u16;
+                    [expand(i4, 4); 4]
+                }
+                TextureFormat::I8 => [block[i]; 4],
+                TextureFormat::IA4 => {
+                    let intensity = expand(block[i] as u16, 4);
+                    [
+                        intensity,
+                        intensity,
+                        intensity,
+                        expand(block[i] as u16 >> 4, 4),
+                    ]
+                }
+                TextureFormat::IA8 => ia8_to_rgba(u16_at(i)),
+                TextureFormat::RGB565 => rgb565_to_rgba(u16_at(i)),
+                TextureFormat::RGB5A3 => rgb5a3_to_rgba(u16_at(i)),
+                TextureFormat::RGBA8 => [
+                    block[i * 2 + 1],
+                    block[32 + i * 2],
+                    block[32 + i * 2 + 1],
+                    block[i * 2],
+                ],
+                TextureFormat::C4 => palette_to_rgba(
+                    palette.unwrap(),
+                    (block[i / 2] >> (4 - (i % 2) * 4)) as usize & 0xF,
+                ),
+                TextureFormat::C8 => palette_to_rgba(palette.unwrap(), block[i] as usize),
+                TextureFormat::C14X2 => {
+                    palette_to_rgba(palette.unwrap(), (u16_at(i) & 0x3FFF) as usize)
+                }
+                TextureFormat::CMPR => {
+                    let (sub_x, sub_y) = (i % 8, i / 8);
+                    let sub_block = (sub_y / 4) * 2 + sub_x / 4;
+                    cmpr_pixels.as_ref().unwrap()[sub_block][(sub_y % 4) * 4 + sub_x % 4]
+                }
+            };
+            out[(y * width + x) * 4..][..4].copy_from_slice(&color);
+        }
+    }
+    Ok(out)
+}
+
+fn intensity(rgba: [u8; 4]) -> u8 {
+    ((rgba[0] as u32 * 299 + rgba[1] as u32 * 587 + rgba[2] as u32 * 114) / 1000) as u8
+}
+
+fn rgba_to_rgb565(rgba: [u8; 4]) -> u16 {
+    (rgba[0] as u16 >> 3) << 11 | (rgba[1] as u16 >> 2) << 5 | rgba[2] as u16 >> 3
+}
+
+fn rgba_to_rgb5a3(rgba: [u8; 4]) -> u16 {
+    if rgba[3] == 0xFF {
+        0x8000 | (rgba[0] as u16 >> 3) << 10 | (rgba[1] as u16 >> 3) << 5 | rgba[2] as u16 >> 3
+    } else {
+        (rgba[3] as u16 >> 5) << 12
+            | (rgba[0] as u16 >> 4) << 8
+            | (rgba[1] as u16 >> 4) << 4
+            | rgba[2] as u16 >> 4
+    }
+}
+
+/// encodes a 4x4 DXT1 block, transparent pixels use the 3 color mode
+fn encode_dxt1(pixels: &[[u8; 4]; 16], out: &mut [u8]) {
+    let has_alpha = pixels.iter().any(|p| p[3] < 0x80);
+    // use the two most distant colors as endpoints
+    let distance = |a: &[u8; 4], b: &[u8; 4]| -> u32 {
+        (0..3)
+            .map(|i| (a[i] as i32 - b[i] as i32).pow(2) as u32)
+            .sum()
+    };
+    let opaque: Vec<&[u8; 4]> = pixels.iter().filter(|p| p[3] >= 0x80).collect();
+    let (mut c0, mut c1) = (0u16, 0u16);
+    let mut max_distance = 0;
+    if let Some(first) = opaque.first() {
+        c0 = rgba_to_rgb565(**first);
+        c1 = c0;
+        for (i, a) in opaque.iter().enumerate() {
+            for b in &opaque[i + 1..] {
+                let d = distance(a, b);
+                if d > max_distance {
+                    max_distance = d;
+                    (c0, c1) = (rgba_to_rgb565(**a), rgba_to_rgb565(**b));
+                }
+            }
+        }
+    }
+    // the order of the endpoints selects the mode
+    if (c0 < c1) != has_alpha && c0 != c1 {
+        (c0, c1) = (c1, c0);
+    }
+    out[0..2].copy_from_slice(&c0.to_be_bytes());
+    out[2..4].copy_from_slice(&c1.to_be_bytes());
+    let mut block = [0; 8];
+    block[..4].copy_from_slice(&out[..4]);
+    let colors = decode_dxt1(&block);
+    for (i, pixel) in pixels.iter().enumerate() {
+        let index = if has_alpha && pixel[3] < 0x80 {
+            3
+        } else {
+            // with equal endpoints the block is in 3 color mode as well
+            (0..if c0 > c1 { 4 } else { 3 })
+                .min_by_key(|idx| distance(&colors[*idx], pixel))
+                .unwrap()
+        };
+        out[4 + i / 4] |= (index as u8) << (6 - (i % 4) * 2);
+    }
+}
+
+/// Encodes RGBA pixels, row by row. Formats with palettes can't be encoded
+pub fn encode_texture(
+    rgba: &[u8],
+    width: usize,
+    height: usize,
+    format: TextureFormat,
+) -> Result<Vec<u8>, TplError> {
+    if format.uses_palette() {
+        return Err(TplError::UnsupportedEncoding(format));
+    }
+    if rgba.len() != width * height * 4 {
+        return Err(TplError::InvalidDataSize {
+            expected: width * height * 4,
+            actual: rgba.len(),
+        });
+    }
+    let (block_width, block_height, block_size) = format.block_layout();
+    let blocks_x = width.div_ceil(block_width);
+    let mut out = vec![0; format.data_size(width, height)];
+    for (block_idx, block) in out.chunks_exact_mut(block_size).enumerate() {
+        let block_x = (block_idx % blocks_x) * block_width;
+        let block_y = (block_idx / blocks_x) * block_height;
+        // pixels outside of the image are transparent black
+        let pixel = |i: usize| -> [u8; 4] {
+            let (x, y) = (block_x + i % block_width, block_y + i / block_width);
+            if x < width && y < height {
+                rgba[(y * width + x) * 4..][..4].try_into().unwrap()
+            } else {
+                [0; 4]
+            }
+        };
+        if format == TextureFormat::CMPR {
+            for sub_block in 0..4 {
+                let mut pixels = [[0; 4]; 16];
+                for (i, p) in pixels.iter_mut().enumerate() {
+                    let x = (sub_block % 2) * 4 + i % 4;
+                    let y = (sub_block / 2) * 4 + i / 4;
+                    *p = pixel(y * 8 + x);
+                }
+                encode_dxt1(&pixels, &mut block[sub_block * 8..][..8]);
+            }
+            continue;
+        }
+        for i in 0..block_width * block_height {
+            let color = pixel(i);
+            match format {
+                TextureFormat::I4 => block[i / 2] |= (intensity(color) >> 4) << (4 - (i % 2) * 4),
+                TextureFormat::I8 => block[i] = intensity(color),
+                TextureFormat::IA4 => block[i] = (color[3] & 0xF0) | intensity(color) >> 4,
+                TextureFormat::IA8 => {
+                    block[i * 2] = color[3];
+                    block[i * 2 + 1] = intensity(color);
+                }
+                TextureFormat::RGB565 => {
+                    block[i * 2..][..2].copy_from_slice(&rgba_to_rgb565(color).to_be_bytes())
+                }
+                TextureFormat::RGB5A3 => {
+                    block[i * 2..][..2].copy_from_slice(&rgba_to_rgb5a3(color).to_be_bytes())
+                }
+                TextureFormat::RGBA8 => {
+                    block[i * 2] = color[3];
+                    block[i * 2 + 1] = color[0];
+                    block[32 + i * 2] = color[1];
+                    block[32 + i * 2 + 1] = color[2];
+                }
+                TextureFormat::C4
+                | TextureFormat::C8
+                | TextureFormat::C14X2
+                | TextureFormat::CMPR => unreachable!(),
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode_texture, PaletteFormat, TextureFormat, Tpl, TplImage, TplPalette};
+
+    fn test_pixels(width: usize, height: usize) -> Vec<u8> {
+        let mut rgba = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let alpha = if x < width / 2 { 0xFF } else { 0 };
+                rgba.extend_from_slice(&[(x * 16) as u8, (y * 16) as u8, 0x80, alpha]);
+            }
+        }
+        rgba
+    }
+
+    #[test]
+    pub fn test_tpl_roundtrip() {
+        let (width, height) = (12, 6);
+        let rgba = test_pixels(width, height);
+        let image = TplImage::from_rgba(12, 6, TextureFormat::RGBA8, &rgba).unwrap();
+        assert_eq!(image.decode_rgba().unwrap(), rgba);
+        // lossy formats keep the transparency
+        for format in [
+            TextureFormat::RGB5A3,
+            TextureFormat::IA8,
+            TextureFormat::IA4,
+            TextureFormat::CMPR,
+        ] {
+            let image = TplImage::from_rgba(12, 6, format, &rgba).unwrap();
+            let decoded = image.decode_rgba().unwrap();
+            assert_eq!(decoded.len(), rgba.len());
+            assert_eq!(decoded[3], 0xFF, "{format:?}");
+            assert_eq!(decoded[11 * 4 + 3], 0, "{format:?}");
+        }
+        let rgb5a3 = TplImage::from_rgba(12, 6, TextureFormat::RGB5A3, &rgba).unwrap();
+        let decoded = rgb5a3.decode_rgba().unwrap();
+        for (a, b) in decoded.iter().zip(rgba.iter()) {
+            assert!(a.abs_diff(*b) < 0x20);
+        }
+
+        let mut paletted = TplImage::from_rgba(8, 4, TextureFormat::I8, &[0; 8 * 4 * 4]).unwrap();
+        paletted.header.format = TextureFormat::C8;
+        paletted.data[1] = 1;
+        assert!(paletted.decode_rgba().is_err());
+        paletted.palette = Some(TplPalette {
+            format: PaletteFormat::RGB5A3,
+            entries: vec![0x0000, 0xFC00],
+        });
+        let decoded = paletted.decode_rgba().unwrap();
+        assert_eq!(&decoded[..8], &[0, 0, 0, 0, 0xFF, 0, 0, 0xFF]);
+
+        let tpl = Tpl {
+            images: vec![rgb5a3, paletted],
+        };
+        let bytes = tpl.to_bytes().unwrap();
+        assert_eq!(&bytes[..4], &[0x00, 0x20, 0xAF, 0x30]);
+        assert_eq!(Tpl::read(&bytes).unwrap().images.len(), 2);
+        let read_tpl = Tpl::read(&bytes).unwrap();
+        for (read, orig) in read_tpl.images.iter().zip(tpl.images.iter()) {
+            assert_eq!(read.data, orig.data);
+            assert_eq!(read.palette, orig.palette);
+        }
+
+        // I4 with two pixels per byte
+        let decoded = decode_texture(&[0xF0; 32], 8, 8, TextureFormat::I4, None).unwrap();
+        assert_eq!(&decoded[..8], &[0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    pub fn test_tpl_corrupt_image_count() {
+        let mut data = 0x0020AF30u32.to_be_bytes().to_vec();
+        data.extend_from_slice(&u32::MAX.to_be_bytes());
+        data.extend_from_slice(&0xCu32.to_be_bytes());
+        assert!(Tpl::read(&data).is_err());
+    }
+}
@@ -0,0 +1,212 @@
+use std::{collections::HashMap, io::Cursor};
+
+use binrw::{binrw, BinReaderExt, BinWriterExt};
+use thiserror::Error;
+
+use crate::{
+    fst::{FstToBytes, FstToBytesError},
+    Fst, FstError, FstNode,
+};
+
+#[derive(Error, Debug)]
+pub enum U8Error {
+    #[error("binrw error: {0}")]
+    BinRW(#[from] binrw::Error),
+    #[error("fst build failed: {0}")]
+    Fst(#[from] FstToBytesError),
+    #[error("{0}")]
+    FstPath(#[from] FstError),
+    #[error("file data is out of bounds for {0}")]
+    DataOutOfBounds(String),
+    #[error("path is invalid or would replace a directory: {0}")]
+    InvalidPath(String),
+}
+
+#[binrw]
+#[brw(big, magic = 0x55AA382Du32)]
+#[derive(Debug, Clone)]
+struct U8Header {
+    root_node_off: u32,
+    /// size of the node and string table
+    header_size: u32,
+    #[brw(pad_after = 16)]
+    data_off: u32,
+}
+
+// nodes always start directly after the header
+const ROOT_NODE_OFFSET: u32 = 0x20;
+
+// only works with power of 2
+fn align_next(num: usize, alignment: usize) -> usize {
+    num.wrapping_add(alignment - 1) & !(alignment - 1)
+}
+
+fn path_key<'a>(iter: impl Iterator<Item = &'a str>) -> String {
+    iter.collect::<Vec<_>>().join("/")
+}
+
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|p| !p.is_empty())
+}
+
+/// U8 archive (usually .arc), which uses the same node layout as the FST
+/// with offsets relative to the start of the archive
+#[derive(Default, Clone)]
+pub struct U8Archive {
+    fst: Fst,
+    /// data of all files, by full path
+    file_data: HashMap<String, Vec<u8>>,
+}
+
+impl U8Archive {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn read(data: &[u8]) -> Result<Self, U8Error> {
+        let mut cursor = Cursor::new(data);
+        let header: U8Header = cursor.read_be()?;
+        let fst = Fst::read_with_shift(&mut cursor, header.root_node_off.into(), 0)?;
+        let mut file_data = HashMap::new();
+        fst.callback_all_files::<U8Error, _>(&mut |path, node| {
+            if let FstNode::File { offset, length, .. } = node {
+                let path = path_key(path.iter().copied());
+                let data = usize::try_from(*offset)
+                    .ok()
+                    .and_then(|offset| data.get(offset..)?.get(..*length as usize))
+                    .ok_or_else(|| U8Error::DataOutOfBounds(path.clone()))?;
+                file_data.insert(path, data.to_vec());
+            }
+            Ok(())
+        })?;
+        Ok(Self { fst, file_data })
+    }
+
+    /// The file tree, offset and length of files are only valid directly after reading
+    pub fn get_fst(&self) -> &Fst {
+        &self.fst
+    }
+
+    pub fn get_file_data(&self, path: &str) -> Option<&[u8]> {
+        self.file_data
+            .get(&path_key(split_path(path)))
+            .map(Vec::as_slice)
+    }
+
+    /// Adds a file at the path, creating all missing directories. If there
+    /// already is a file, the data is replaced and the old data is returned
+    pub fn add_file(&mut self, path: &str, data: Vec<u8>) -> Result<Option<Vec<u8>>, U8Error> {
+        let mut parts: Vec<&str> = split_path(path).collect();
+        let name = parts
+            .pop()
+            .ok_or_else(|| U8Error::InvalidPath(path.to_string()))?;
+        let node = FstNode::File {
+            name: name.to_string(),
+            offset: 0,
+            length: data.len() as u32,
+        };
+        // don't replace entire directories
+        if self.fst.find_node_path(path).is_some_and(FstNode::is_dir) {
+            return Err(U8Error::InvalidPath(path.to_string()));
+        }
+        self.fst.add_node_iter(parts.into_iter(), node)?;
+        Ok(self.file_data.insert(path_key(split_path(path)), data))
+    }
+
+    /// Replaces the data of an existing file, returns the old data if the file exists
+    pub fn replace_file(&mut self, path: &str, data: Vec<u8>) -> Option<Vec<u8>> {
+        match self.fst.find_node_path_mut(path)? {
+            FstNode::File { length, .. } => {
+                *length = data.len() as u32;
+                self.file_data.insert(path_key(split_path(path)), data)
+            }
+            FstNode::Directory { .. } => None,
+        }
+    }
+
+    /// Removes a file or directory including all its files
+    pub fn remove_node(&mut self, path: &str) -> Option<FstNode> {
+        let node = self.fst.remove_node_path(path).ok()?;
+        let key = path_key(split_path(path));
+        match &node {
+            FstNode::File { .. } => {
+                self.file_data.remove(&key);
+            }
+            FstNode::Directory { .. } => {
+                let prefix = key + "/";
+                self.file_data.retain(|path, _| !path.starts_with(&prefix));
+            }
+        }
+        Some(node)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, U8Error> {
+        let mut fst = FstToBytes::try_from_unchecked(self.fst.clone())?;
+        let data_off = align_next(ROOT_NODE_OFFSET as usize + fst.get_byte_size(), 0x20);
+        let mut files = Vec::new();
+        let mut current_off = data_off;
+        fst.callback_all_files_mut::<U8Error, _>(&mut |path, offset, length| {
+            // directories are only created when adding files, so data is always present
+            let data = self
+                .file_data
+                .get(&path_key(path.iter().map(String::as_str)))
+                .map_or(&[][..], Vec::as_slice);
+            current_off = align_next(current_off, 0x20);
+            *offset = current_off as u64;
+            *length = data.len() as u32;
+            current_off += data.len();
+            files.push(data);
+            Ok(())
+        })?;
+        let mut out = Cursor::new(Vec::with_capacity(current_off));
+        out.write_be(&U8Header {
+            root_node_off: ROOT_NODE_OFFSET,
+            header_size: fst.get_byte_size() as u32,
+            data_off: data_off as u32,
+        })?;
+        fst.write_to_with_shift(&mut out, 0)?;
+        let mut out = out.into_inner();
+        for data in files {
+            out.resize(align_next(out.len(), 0x20), 0);
+            out.extend_from_slice(data);
+        }
+        // pad the archive itself to 0x20
+        out.resize(align_next(out.len(), 0x20), 0);
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::U8Archive;
+
+    #[test]
+    pub fn test_roundtrip() {
+        let mut arc = U8Archive::new();
+        assert!(arc.add_file("arc/file1", vec![1; 0x35]).unwrap().is_none());
+        assert!(arc.add_file("arc/dir/file2", vec![2; 3]).unwrap().is_none());
+        assert!(arc
+            .add_file("arc/dir/file3", vec![3; 0x40])
+            .unwrap()
+            .is_none());
+        assert!(arc.add_file("arc/file1/nope", vec![]).is_err());
+        assert!(arc.add_file("arc/dir", vec![]).is_err());
+        assert_eq!(
+            arc.replace_file("arc/dir/file3", vec![4; 5]),
+            Some(vec![3; 0x40])
+        );
+
+        let bytes = arc.to_bytes().unwrap();
+        assert_eq!(&bytes[..4], &[0x55, 0xAA, 0x38, 0x2D]);
+        let mut arc = U8Archive::read(&bytes).unwrap();
+        assert_eq!(arc.get_file_data("arc/file1"), Some(&[1; 0x35][..]));
+        assert_eq!(arc.get_file_data("arc/dir/file2"), Some(&[2; 3][..]));
+        assert_eq!(arc.get_file_data("arc/dir/file3"), Some(&[4; 5][..]));
+
+        assert!(arc.remove_node("arc/dir").is_some());
+        assert_eq!(arc.get_file_data("arc/dir/file2"), None);
+        let arc = U8Archive::read(&arc.to_bytes().unwrap()).unwrap();
+        assert!(arc.get_fst().find_node_path("arc/dir").is_none());
+        assert_eq!(arc.get_file_data("arc/file1"), Some(&[1; 0x35][..]));
+    }
+}